@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use std::sync::mpsc;
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::SharedConfig;
+use crate::control::CONTROL;
+use crate::health::HEALTH;
+use crate::metrics::METRICS;
+use crate::sync::{self, SecretOp};
+
+type AdminResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+// Body of the request to sync one secret
+#[derive(Deserialize)]
+struct SyncRequest {
+    mount: String,
+    path: String,
+    namespace: Option<String>,
+}
+
+// Serves the admin HTTP endpoints:
+// /healthz - 200 if all workers are alive
+// /readyz - 200 if the clients are authenticated, the tokens are renewed and the first full sync
+// is finished
+// /metrics - Prometheus metrics
+// /api/... - admin API, requires the admin token
+pub fn admin_server(
+    server: Server,
+    config: SharedConfig,
+    tx: mpsc::Sender<SecretOp>,
+    full_sync_trigger: mpsc::Sender<()>,
+) {
+    info!("Admin worker started");
+    for mut request in server.incoming_requests() {
+        let url = request.url().to_string();
+        let response = match (request.method(), url.as_str()) {
+            (Method::Get, "/healthz") => problems_response(HEALTH.health_problems()),
+            (Method::Get, "/readyz") => problems_response(HEALTH.ready_problems()),
+            (Method::Get, "/metrics") => Response::from_string(METRICS.gather())
+                .with_header(Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap()),
+            (_, path) if path.starts_with("/api/") => {
+                let token = config.get().admin.as_ref().and_then(|admin| admin.token.clone());
+                match token {
+                    Some(token) if authorized(&request, &token) => {
+                        api(&mut request, &config, &tx, &full_sync_trigger)
+                    },
+                    Some(_) => json_response(401, json!({"error": "unauthorized"})),
+                    None => json_response(403, json!({"error": "admin API is disabled, admin.token is not set"})),
+                }
+            },
+            _ => Response::from_string("not found\n").with_status_code(404),
+        };
+        respond(request, response);
+    }
+}
+
+fn api(
+    request: &mut Request,
+    config: &SharedConfig,
+    tx: &mpsc::Sender<SecretOp>,
+    full_sync_trigger: &mpsc::Sender<()>,
+) -> AdminResponse {
+    let url = request.url().to_string();
+    match (request.method(), url.as_str()) {
+        (Method::Get, "/api/status") => json_response(200, status_json()),
+        (Method::Post, "/api/full-sync") => {
+            info!("Full sync triggered by the admin API");
+            let _ = full_sync_trigger.send(());
+            json_response(202, json!({"result": "full sync triggered"}))
+        },
+        (Method::Post, "/api/pause") => {
+            info!("Sync paused by the admin API");
+            CONTROL.set_paused(true);
+            json_response(200, json!({"result": "paused"}))
+        },
+        (Method::Post, "/api/resume") => {
+            info!("Sync resumed by the admin API");
+            CONTROL.set_paused(false);
+            json_response(200, json!({"result": "resumed"}))
+        },
+        (Method::Post, "/api/sync") => {
+            let body: Result<SyncRequest, _> = serde_json::from_reader(request.as_reader());
+            let path = body.map_err(|error| error.to_string()).and_then(|body| {
+                sync::requested_secret_path(&config.get().src, &body.mount, &body.path, body.namespace.as_deref())
+            });
+            match path {
+                Ok(path) => {
+                    info!(mount = path.mount.as_str(), src_path = path.path.as_str(); "Secret sync requested by the admin API");
+                    sync::send_op(tx, SecretOp::Update(path));
+                    json_response(202, json!({"result": "queued"}))
+                },
+                Err(error) => json_response(400, json!({"error": error})),
+            }
+        },
+        _ => json_response(404, json!({"error": "not found"})),
+    }
+}
+
+fn status_json() -> Value {
+    json!({
+        "paused": CONTROL.paused(),
+        "queue_depth": METRICS.queue_depth(),
+        "last_full_sync": CONTROL.last_full_sync(),
+        "health_problems": HEALTH.health_problems(),
+        "ready_problems": HEALTH.ready_problems(),
+    })
+}
+
+// Checks the "Authorization: Bearer <token>" header
+fn authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    request.headers().iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .any(|header| {
+            let actual = header.value.as_str();
+            actual.len() == expected.len() && openssl::memcmp::eq(actual.as_bytes(), expected.as_bytes())
+        })
+}
+
+// Admin address to connect to from the same host, for the bind address
+// Example: "0.0.0.0:8203" -> "127.0.0.1:8203"
+pub fn local_address(bind: &str) -> String {
+    match bind.rsplit_once(':') {
+        Some(("0.0.0.0", port)) | Some(("", port)) => format!("127.0.0.1:{}", port),
+        Some(("[::]", port)) => format!("[::1]:{}", port),
+        _ => bind.to_string(),
+    }
+}
+
+// Prints the health and readiness of a running vault-sync and, if the admin token is set, the sync
+// status. Returns true if it is ready.
+pub fn status(address: &str, token: Option<&str>) -> Result<bool, Box<dyn Error>> {
+    let (healthz_status, healthz) = get(address, "/healthz", None)?;
+    let (readyz_status, readyz) = get(address, "/readyz", None)?;
+    println!("Healthy: {}", healthz.trim());
+    println!("Ready: {}", readyz.trim());
+    if let Some(token) = token {
+        let (status, body) = get(address, "/api/status", Some(token))?;
+        if status != 200 {
+            return Err(format!("GET /api/status: {} {}", status, body.trim()).into());
+        }
+        let body: Value = serde_json::from_str(&body)?;
+        println!("Paused: {}", body["paused"]);
+        println!("Queue depth: {}", body["queue_depth"]);
+        match body["last_full_sync"].as_object() {
+            Some(last) => println!(
+                "Last full sync: {}, created/updated: {}, deleted: {}",
+                last["finished"].as_str().unwrap_or_default(), last["updated"], last["deleted"],
+            ),
+            None => println!("Last full sync: none"),
+        }
+    }
+    Ok(healthz_status == 200 && readyz_status == 200)
+}
+
+// Sends a GET request to the admin endpoint, returns the status code and the body
+fn get(address: &str, path: &str, token: Option<&str>) -> Result<(u16, String), Box<dyn Error>> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n", path, address)?;
+    if let Some(token) = token {
+        write!(stream, "Authorization: Bearer {}\r\n", token)?;
+    }
+    write!(stream, "\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| format!("invalid response from {}", address))?;
+    Ok((status, body.to_string()))
+}
+
+fn problems_response(problems: Vec<String>) -> AdminResponse {
+    if problems.is_empty() {
+        Response::from_string("ok\n")
+    } else {
+        Response::from_string(format!("{}\n", problems.join("\n"))).with_status_code(503)
+    }
+}
+
+fn json_response(status: u16, body: Value) -> AdminResponse {
+    Response::from_string(format!("{}\n", body))
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn respond(request: Request, response: AdminResponse) {
+    if let Err(error) = request.respond(response) {
+        warn!(error:%; "Failed to send admin response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin::local_address;
+
+    #[test]
+    fn test_local_address() {
+        assert_eq!(local_address("0.0.0.0:8203"), "127.0.0.1:8203");
+        assert_eq!(local_address(":8203"), "127.0.0.1:8203");
+        assert_eq!(local_address("[::]:8203"), "[::1]:8203");
+        assert_eq!(local_address("10.0.0.1:8203"), "10.0.0.1:8203");
+    }
+}
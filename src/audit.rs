@@ -14,4 +14,14 @@ pub struct Request {
     pub operation: String,
     pub mount_type: Option<String>,
     pub path: String,
+    pub namespace: Option<Namespace>,
+}
+
+// Vault Enterprise and OpenBao namespace of the request, for the root namespace the path is empty
+// or not set
+#[derive(Deserialize, Debug)]
+pub struct Namespace {
+    #[allow(dead_code)]
+    pub id: Option<String>,
+    pub path: Option<String>,
 }
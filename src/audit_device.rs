@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use hashicorp_vault::client::{EndpointResponse, HttpVerb};
+use log::{debug, info, warn};
+use serde_json::{json, Map, Value};
+
+use crate::config::{parse_bind, BindAddress, VaultSyncConfig};
+use crate::vault::{retry_on_timeout, VaultClient};
+
+pub fn audit_device_exists(name: &str, client: Arc<Mutex<VaultClient>>) -> bool {
+    let client = root_client(&client);
+    let name = format!("{}/", name);
+    match audit_devices(&client) {
+        Ok(devices) => devices.contains_key(&name),
+        Err(error) => {
+            warn!(error:%; "GET sys/audit failed");
+            false
+        }
+    }
+}
+
+// Enables the socket audit device that sends the audit log to this vault-sync, or checks that
+// the existing device has the expected options
+pub fn enable_audit_device(config: &VaultSyncConfig, client: Arc<Mutex<VaultClient>>) -> Result<(), Box<dyn Error>> {
+    let expected = audit_device_options(config);
+    let client = root_client(&client);
+    let devices = audit_devices(&client)?;
+    let name = format!("{}/", &config.id);
+    match devices.get(&name) {
+        Some(device) => {
+            let mismatches = audit_device_mismatches(device, &expected);
+            if mismatches.is_empty() {
+                info!(audit_device = config.id.as_str(); "Audit device exists");
+            } else {
+                warn!(audit_device = config.id.as_str(), mismatches:% = mismatches.join(", "); "Audit device exists, but has unexpected options");
+            }
+        },
+        None => {
+            // If the only audit device cannot write, then Vault stops responding to requests
+            if devices.is_empty() {
+                return Err(format!(
+                    "refusing to enable audit device {}, it would be the only audit device",
+                    &config.id,
+                ).into());
+            }
+            info!(audit_device = config.id.as_str(); "Enabling audit device");
+            let body = json!({
+                "type": "socket",
+                "description": "vault-sync",
+                "options": expected,
+            });
+            retry_on_timeout(|| client.call_endpoint::<Value>(
+                HttpVerb::PUT,
+                &format!("sys/audit/{}", &config.id),
+                None,
+                Some(&body.to_string()),
+            ))?;
+        },
+    }
+    Ok(())
+}
+
+pub fn disable_audit_device(name: &str, client: Arc<Mutex<VaultClient>>) {
+    info!(audit_device = name; "Disabling audit device");
+    let client = root_client(&client);
+    if let Err(error) = retry_on_timeout(|| client.call_endpoint::<Value>(HttpVerb::DELETE, &format!("sys/audit/{}", name), None, None)) {
+        warn!(audit_device = name, error:%; "Failed to disable audit device");
+    }
+}
+
+// Locks the client for the sys/audit requests. Audit devices exist only in the root namespace,
+// while the sync workers switch the namespace of the shared client.
+fn root_client(client: &Arc<Mutex<VaultClient>>) -> MutexGuard<'_, VaultClient> {
+    let mut client = client.lock().unwrap();
+    client.namespace = None;
+    client
+}
+
+// Returns the enabled audit devices by path (with the trailing slash)
+fn audit_devices(client: &VaultClient) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let response = retry_on_timeout(|| client.call_endpoint::<Value>(HttpVerb::GET, "sys/audit", None, None))?;
+    debug!(response:?; "GET sys/audit");
+    if let EndpointResponse::VaultResponse(response) = response {
+        if let Some(Value::Object(map)) = response.data {
+            return Ok(map);
+        }
+    }
+    Ok(Map::new())
+}
+
+// Options for the socket audit device: address and socket type derived from the configuration,
+// then the custom options
+fn audit_device_options(config: &VaultSyncConfig) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    let audit_device = config.audit_device.as_ref().unwrap();
+    // The address is already checked by the config validation
+    let (socket_type, bind_address) = match parse_bind(config.bind.as_ref().unwrap()).unwrap() {
+        BindAddress::Tcp(addr) => ("tcp", addr),
+        BindAddress::Udp(addr) => ("udp", addr),
+        BindAddress::Unix(path) => ("unix", path),
+    };
+    let address = audit_device.address.clone().unwrap_or(bind_address);
+    options.insert("address".to_string(), address);
+    options.insert("socket_type".to_string(), socket_type.to_string());
+    for (key, value) in &audit_device.options {
+        options.insert(key.clone(), value.clone());
+    }
+    options
+}
+
+// Returns the list of the device properties that do not match the expected ones
+fn audit_device_mismatches(device: &Value, expected: &BTreeMap<String, String>) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if device["type"] != "socket" {
+        mismatches.push(format!("type={}", device["type"]));
+    }
+    for (key, value) in expected {
+        let actual = &device["options"][key];
+        if actual != value.as_str() {
+            mismatches.push(format!("{}={} (expected {})", key, actual, value));
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use crate::audit_device::{audit_device_mismatches, audit_device_options};
+    use crate::config::VaultSyncConfig;
+
+    fn config(bind: &str, audit_device: &str) -> VaultSyncConfig {
+        let yaml = format!(
+            r#"
+                id: vault-sync
+                full_sync_interval: 60
+                bind: {}
+                audit_device:
+                  {}
+                src:
+                  url: http://127.0.0.1:8200/
+                dst:
+                  url: http://127.0.0.1:8200/
+            "#,
+            bind,
+            audit_device,
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_audit_device_options() {
+        let options = audit_device_options(&config("0.0.0.0:8202", "address: vault-sync:8202"));
+        assert_eq!(options["address"], "vault-sync:8202");
+        assert_eq!(options["socket_type"], "tcp");
+
+        let options = audit_device_options(&config("unix:///run/vault-sync.sock", "options: { format: jsonx }"));
+        assert_eq!(options["address"], "/run/vault-sync.sock");
+        assert_eq!(options["socket_type"], "unix");
+        assert_eq!(options["format"], "jsonx");
+    }
+
+    #[test]
+    fn test_audit_device_mismatches() {
+        let expected = BTreeMap::from([
+            ("address".to_string(), "vault-sync:8202".to_string()),
+            ("socket_type".to_string(), "tcp".to_string()),
+        ]);
+        let device = json!({
+            "type": "socket",
+            "options": {"address": "vault-sync:8202", "socket_type": "tcp", "format": "json"},
+        });
+        assert!(audit_device_mismatches(&device, &expected).is_empty());
+
+        let device = json!({
+            "type": "socket",
+            "options": {"address": "vault-sync:8203", "socket_type": "tcp"},
+        });
+        assert_eq!(audit_device_mismatches(&device, &expected).len(), 1);
+
+        let device = json!({"type": "file", "options": {"file_path": "stdout"}});
+        assert_eq!(audit_device_mismatches(&device, &expected).len(), 3);
+    }
+}
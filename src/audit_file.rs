@@ -0,0 +1,293 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::config::SharedConfig;
+use crate::shutdown;
+use crate::sync::{self, AuditFilter, SecretOp};
+
+// Follows a Vault file audit device log, similar to `tail -F`.
+// The file is reopened when it is rotated (replaced with a new file) and read from the beginning
+// when it is truncated. The position of the last complete line is optionally saved to
+// a checkpoint file, so vault-sync can continue from the same position after restart.
+pub struct AuditFileTail {
+    path: String,
+    checkpoint: Option<String>,
+    reader: Option<BufReader<File>>,
+    inode: u64,
+    offset: u64,
+    // Position to start from when the file is opened for the first time
+    start: Option<(u64, u64)>,
+    // Skip the existing entries on the first open, only if the file existed at startup
+    skip_existing: bool,
+    // Position saved to the checkpoint file
+    saved: (u64, u64),
+}
+
+impl AuditFileTail {
+    pub fn new(path: &str, checkpoint: Option<&str>) -> AuditFileTail {
+        let start = checkpoint.and_then(|checkpoint| match load_checkpoint(checkpoint) {
+            Ok(position) => position,
+            Err(error) => {
+                warn!(checkpoint, error:%; "Failed to load checkpoint");
+                None
+            }
+        });
+        AuditFileTail {
+            path: path.to_string(),
+            checkpoint: checkpoint.map(|s| s.to_string()),
+            reader: None,
+            inode: 0,
+            offset: 0,
+            start,
+            skip_existing: fs::metadata(path).is_ok(),
+            saved: (0, 0),
+        }
+    }
+
+    // Returns all complete lines appended to the file since the previous call. The position is
+    // saved only by `save_checkpoint`, after the lines are processed.
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            if self.reader.is_none() && !self.open()? {
+                break;
+            }
+            self.read_available(&mut lines)?;
+
+            let metadata = match fs::metadata(&self.path) {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    // Rotated, but the new file is not created yet
+                    break;
+                },
+                Err(error) => return Err(error),
+            };
+            if metadata.ino() != self.inode {
+                info!(path = self.path.as_str(); "Audit log rotated");
+                self.reader = None;
+                self.offset = 0;
+                continue;
+            }
+            if metadata.len() < self.offset {
+                info!(path = self.path.as_str(); "Audit log truncated");
+                self.seek(0)?;
+                continue;
+            }
+            break;
+        }
+        Ok(lines)
+    }
+
+    // Opens the file, returns false if the file does not exist
+    fn open(&mut self) -> io::Result<bool> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        let metadata = file.metadata()?;
+        self.inode = metadata.ino();
+        self.reader = Some(BufReader::new(file));
+        let offset = match self.start.take() {
+            Some((inode, offset)) if inode == self.inode && offset <= metadata.len() => offset,
+            Some(_) => 0,
+            // No checkpoint, skip the existing entries, the full sync takes care of them.
+            // A file created after startup is read from the beginning.
+            None if self.skip_existing => metadata.len(),
+            None => 0,
+        };
+        self.skip_existing = false;
+        info!(path = self.path.as_str(), offset; "Reading audit log");
+        self.seek(offset)?;
+        Ok(true)
+    }
+
+    fn seek(&mut self, offset: u64) -> io::Result<()> {
+        if let Some(reader) = &mut self.reader {
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+        self.offset = offset;
+        Ok(())
+    }
+
+    fn read_available(&mut self, lines: &mut Vec<String>) -> io::Result<()> {
+        let reader = self.reader.as_mut().unwrap();
+        loop {
+            let mut buf = Vec::new();
+            let n = reader.read_until(b'\n', &mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            if buf.last() != Some(&b'\n') {
+                // Partial line, the rest of it is not written yet
+                reader.seek(SeekFrom::Start(self.offset))?;
+                return Ok(());
+            }
+            self.offset += n as u64;
+            lines.push(String::from_utf8_lossy(&buf).into_owned());
+        }
+    }
+
+    // Saves the position after the lines returned by `read_lines`, if it changed
+    pub fn save_checkpoint(&mut self) -> io::Result<()> {
+        let position = (self.inode, self.offset);
+        if let Some(checkpoint) = self.checkpoint.as_ref().filter(|_| position != self.saved) {
+            let tmp = format!("{}.tmp", checkpoint);
+            fs::write(&tmp, format!("{} {}\n", position.0, position.1))?;
+            fs::rename(&tmp, checkpoint)?;
+            self.saved = position;
+        }
+        Ok(())
+    }
+}
+
+// Checkpoint file contains the inode and the offset, separated by a space
+fn load_checkpoint(file_name: &str) -> io::Result<Option<(u64, u64)>> {
+    let content = match fs::read_to_string(file_name) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let parts: Vec<&str> = content.split_whitespace().collect();
+    if parts.len() != 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected inode and offset"));
+    }
+    let inode = parts[0].parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid inode"))?;
+    let offset = parts[1].parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid offset"))?;
+    Ok(Some((inode, offset)))
+}
+
+pub fn audit_file_sync(config: SharedConfig, tx: mpsc::Sender<SecretOp>) {
+    let current_config = config.get();
+    let audit_file = current_config.audit_file.as_ref().unwrap();
+    let interval = Duration::from_millis(audit_file.poll_interval_ms);
+    let mut tail = AuditFileTail::new(&audit_file.path, audit_file.checkpoint.as_deref());
+    info!("AuditFile worker started");
+    while !shutdown::requested() {
+        match tail.read_lines() {
+            Ok(lines) => {
+                let filter = AuditFilter::new(&config.get().src);
+                for line in &lines {
+                    sync::audit_log_line(&filter, line, &tx);
+                }
+                // Only after the operations are queued, so a crash does not lose them
+                if let Err(error) = tail.save_checkpoint() {
+                    warn!(error:%; "Failed to save checkpoint");
+                }
+                if !lines.is_empty() {
+                    continue;
+                }
+            },
+            Err(error) => {
+                warn!(path = audit_file.path.as_str(), error:%; "Failed to read audit log");
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::audit_file::{load_checkpoint, AuditFileTail};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vault-sync-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &PathBuf, data: &str) {
+        let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_tail_skips_existing_entries() {
+        let dir = temp_dir("skip");
+        let log = dir.join("audit.log");
+        append(&log, "old\n");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), None);
+        assert!(tail.read_lines().unwrap().is_empty());
+        append(&log, "new\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["new\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_created_after_start() {
+        let dir = temp_dir("created");
+        let log = dir.join("audit.log");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), None);
+        assert!(tail.read_lines().unwrap().is_empty());
+        append(&log, "first\n");
+        append(&log, "second\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["first\n", "second\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_partial_line() {
+        let dir = temp_dir("partial");
+        let log = dir.join("audit.log");
+        append(&log, "");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), None);
+        assert!(tail.read_lines().unwrap().is_empty());
+        append(&log, "first\nsec");
+        assert_eq!(tail.read_lines().unwrap(), vec!["first\n"]);
+        append(&log, "ond\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["second\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_rotation_and_truncation() {
+        let dir = temp_dir("rotate");
+        let log = dir.join("audit.log");
+        append(&log, "");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), None);
+        assert!(tail.read_lines().unwrap().is_empty());
+
+        append(&log, "one\n");
+        fs::rename(&log, dir.join("audit.log.1")).unwrap();
+        append(&log, "two\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["one\n", "two\n"]);
+
+        fs::write(&log, "").unwrap();
+        append(&log, "3\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["3\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_checkpoint() {
+        let dir = temp_dir("checkpoint");
+        let log = dir.join("audit.log");
+        let checkpoint = dir.join("audit.offset");
+        append(&log, "");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), checkpoint.to_str());
+        assert!(tail.read_lines().unwrap().is_empty());
+        append(&log, "one\n");
+        assert_eq!(tail.read_lines().unwrap(), vec!["one\n"]);
+        // Not saved until the lines are processed
+        assert!(load_checkpoint(checkpoint.to_str().unwrap()).unwrap().is_none());
+        tail.save_checkpoint().unwrap();
+        assert_eq!(load_checkpoint(checkpoint.to_str().unwrap()).unwrap().unwrap().1, 4);
+
+        // Restart, continue from the checkpoint
+        append(&log, "two\n");
+        let mut tail = AuditFileTail::new(log.to_str().unwrap(), checkpoint.to_str());
+        assert_eq!(tail.read_lines().unwrap(), vec!["two\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
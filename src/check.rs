@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use hashicorp_vault::client::{EndpointResponse, HttpVerb};
+use serde_json::{json, Value};
+
+use crate::config::{get_backends, EngineVersion, VaultSyncConfig};
+use crate::sync::{dst_namespace, normalize_prefix, source_namespaces, src_namespace};
+use crate::vault::VaultClient;
+
+// Name of the secret to check the capabilities for, the secret does not need to exist
+const CHECK_SECRET: &str = "vault-sync-check";
+
+struct CheckResult {
+    side: &'static str,
+    namespace: Option<String>,
+    path: String,
+    required: String,
+    error: Option<String>,
+}
+
+// Checks that the source and destination mounts exist, have the configured KV version, and
+// the tokens have the capabilities vault-sync needs. Prints the results, returns true if all
+// checks passed.
+pub fn check(config: &VaultSyncConfig, src_client: Arc<Mutex<VaultClient>>, dst_client: Arc<Mutex<VaultClient>>) -> bool {
+    let mut results = Vec::new();
+
+    let src_prefix = normalize_prefix(&config.src.prefix);
+    let dst_prefix = normalize_prefix(&config.dst.prefix);
+    let src_mounts = get_backends(&config.src.backend);
+    let dst_mounts = get_backends(&config.dst.backend);
+    for child_namespace in source_namespaces(&config.src) {
+        let namespace = src_namespace(&config.src, &child_namespace);
+        let mut client = src_client.lock().unwrap();
+        client.namespace = namespace.clone();
+        for mount in &src_mounts {
+            let paths = match config.src.version {
+                EngineVersion::V1 => vec![
+                    (format!("{}/{}", mount, &src_prefix), vec!["list"]),
+                    (format!("{}/{}{}", mount, &src_prefix, CHECK_SECRET), vec!["read"]),
+                ],
+                EngineVersion::V2 => vec![
+                    (format!("{}/metadata/{}", mount, &src_prefix), vec!["list"]),
+                    (format!("{}/data/{}{}", mount, &src_prefix, CHECK_SECRET), vec!["read"]),
+                ],
+            };
+            check_mount(&client, "src", &namespace, mount, &config.src.version, paths, &mut results);
+        }
+    }
+
+    let dst_required = if config.dst.patch {
+        vec!["create", "read", "update", "delete", "patch"]
+    } else {
+        vec!["create", "read", "update", "delete"]
+    };
+    for child_namespace in source_namespaces(&config.src) {
+        let namespace = dst_namespace(config, &child_namespace);
+        let mut client = dst_client.lock().unwrap();
+        client.namespace = namespace.clone();
+        for mount in &dst_mounts {
+            let paths = match config.dst.version {
+                EngineVersion::V1 => vec![
+                    (format!("{}/{}{}", mount, &dst_prefix, CHECK_SECRET), dst_required.clone()),
+                ],
+                EngineVersion::V2 => vec![
+                    (format!("{}/data/{}{}", mount, &dst_prefix, CHECK_SECRET), dst_required.clone()),
+                    (format!("{}/metadata/{}{}", mount, &dst_prefix, CHECK_SECRET), vec!["create", "update", "delete"]),
+                ],
+            };
+            check_mount(&client, "dst", &namespace, mount, &config.dst.version, paths, &mut results);
+        }
+    }
+
+    print_results(&results);
+    results.iter().all(|result| result.error.is_none())
+}
+
+fn check_mount(
+    client: &VaultClient,
+    side: &'static str,
+    namespace: &Option<String>,
+    mount: &str,
+    version: &EngineVersion,
+    paths: Vec<(String, Vec<&str>)>,
+    results: &mut Vec<CheckResult>,
+) {
+    let error = match mount_info(client, mount) {
+        Ok(info) => mount_version_error(&info, version),
+        Err(error) => Some(error.to_string()),
+    };
+    results.push(CheckResult {
+        side,
+        namespace: namespace.clone(),
+        path: format!("{}/", mount),
+        required: format!("kv v{}", version.clone() as u8),
+        error,
+    });
+    for (path, required) in paths {
+        let error = match capabilities(client, &path) {
+            Ok(capabilities) => {
+                let missing = missing_capabilities(&capabilities, &required);
+                if missing.is_empty() { None } else { Some(format!("missing {}", missing.join(", "))) }
+            },
+            Err(error) => Some(error.to_string()),
+        };
+        results.push(CheckResult {
+            side,
+            namespace: namespace.clone(),
+            path,
+            required: required.join(", "),
+            error,
+        });
+    }
+}
+
+// Returns the mount type and options, available to any token with a capability on the mount
+fn mount_info(client: &VaultClient, mount: &str) -> Result<Value, Box<dyn Error>> {
+    let response = client.call_endpoint::<Value>(HttpVerb::GET, &format!("sys/internal/ui/mounts/{}", mount), None, None)?;
+    match response {
+        EndpointResponse::VaultResponse(response) => Ok(response.data.unwrap_or_default()),
+        EndpointResponse::Empty => Err("empty response".into()),
+    }
+}
+
+fn mount_version_error(info: &Value, version: &EngineVersion) -> Option<String> {
+    let mount_type = info["type"].as_str().unwrap_or_default();
+    if mount_type != "kv" && mount_type != "generic" {
+        return Some(format!("not a KV secrets engine: {}", mount_type));
+    }
+    // KV v1 mounts may have no version option
+    let mount_version = info["options"]["version"].as_str().unwrap_or("1");
+    if mount_version != (version.clone() as u8).to_string() {
+        return Some(format!("KV secrets engine version is {}", mount_version));
+    }
+    None
+}
+
+fn capabilities(client: &VaultClient, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let body = json!({"paths": [path]});
+    let response = client.call_endpoint::<Value>(HttpVerb::POST, "sys/capabilities-self", None, Some(&body.to_string()))?;
+    if let EndpointResponse::VaultResponse(response) = response {
+        if let Some(data) = response.data {
+            if let Some(capabilities) = data[path].as_array().or(data["capabilities"].as_array()) {
+                return Ok(capabilities.iter().filter_map(|c| c.as_str().map(|c| c.to_string())).collect());
+            }
+        }
+    }
+    Err("no capabilities in response".into())
+}
+
+fn missing_capabilities(capabilities: &[String], required: &[&str]) -> Vec<String> {
+    if capabilities.iter().any(|c| c == "root") {
+        return Vec::new();
+    }
+    required.iter()
+        .filter(|r| !capabilities.iter().any(|c| c == *r))
+        .map(|r| r.to_string())
+        .collect()
+}
+
+fn print_results(results: &[CheckResult]) {
+    let rows: Vec<[String; 5]> = results.iter().map(|result| [
+        result.side.to_string(),
+        result.namespace.clone().unwrap_or_else(|| "-".to_string()),
+        result.path.clone(),
+        result.required.clone(),
+        match &result.error {
+            None => "PASS".to_string(),
+            Some(error) => format!("FAIL: {}", error),
+        },
+    ]).collect();
+    let header = ["SIDE", "NAMESPACE", "PATH", "REQUIRED", "RESULT"].map(|s| s.to_string());
+    let mut widths = [0; 5];
+    for row in rows.iter().chain([&header]) {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+    for row in [&header].into_iter().chain(rows.iter()) {
+        let line: Vec<String> = row.iter().enumerate().map(|(i, cell)| format!("{:width$}", cell, width = widths[i])).collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::check::{missing_capabilities, mount_version_error};
+    use crate::config::EngineVersion;
+
+    #[test]
+    fn test_missing_capabilities() {
+        let capabilities = vec!["read".to_string(), "list".to_string()];
+        assert!(missing_capabilities(&capabilities, &["list", "read"]).is_empty());
+        assert_eq!(missing_capabilities(&capabilities, &["create", "read", "delete"]), vec!["create", "delete"]);
+        assert!(missing_capabilities(&["root".to_string()], &["create"]).is_empty());
+        assert_eq!(missing_capabilities(&["deny".to_string()], &["read"]), vec!["read"]);
+    }
+
+    #[test]
+    fn test_mount_version_error() {
+        let info = json!({"type": "kv", "options": {"version": "2"}});
+        assert!(mount_version_error(&info, &EngineVersion::V2).is_none());
+        assert!(mount_version_error(&info, &EngineVersion::V1).is_some());
+        let info = json!({"type": "kv", "options": null});
+        assert!(mount_version_error(&info, &EngineVersion::V1).is_none());
+        let info = json!({"type": "pki"});
+        assert!(mount_version_error(&info, &EngineVersion::V2).is_some());
+    }
+}
@@ -1,14 +1,21 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fs::File;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
 
 use serde::{Deserialize, Serialize, Serializer};
 use serde_repr::*;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::listener::parse_peer;
+use crate::schedule::{self, TimeWindow};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
+#[allow(clippy::enum_variant_names)]
 pub enum VaultAuthMethod {
     TokenAuth {
         #[serde(serialize_with = "sanitize")]
@@ -19,13 +26,87 @@ pub enum VaultAuthMethod {
         role_id: String,
         #[serde(serialize_with = "sanitize")]
         secret_id: String,
-    }
+        // The secret_id is a response wrapping token
+        #[serde(default)]
+        secret_id_wrapped: bool,
+    },
+    // The files are read on every login, for example when Vault Agent or the CSI driver rotates them
+    TokenFileAuth {
+        token_file: String,
+    },
+    AppRoleFileAuth {
+        #[serde(default, serialize_with = "sanitize_option")]
+        role_id: Option<String>,
+        role_id_file: Option<String>,
+        secret_id_file: String,
+        #[serde(default)]
+        secret_id_wrapped: bool,
+    },
+    KubernetesAuth {
+        kubernetes: KubernetesAuth,
+    },
+    JwtAuth {
+        jwt: JwtAuth,
+    },
+    CertAuth {
+        cert: CertAuth,
+    },
+    UserpassAuth {
+        userpass: PasswordAuth,
+    },
+    LdapAuth {
+        ldap: PasswordAuth,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KubernetesAuth {
+    pub role: String,
+    #[serde(default = "default_kubernetes_mount")]
+    pub mount: String,
+    #[serde(default = "default_kubernetes_jwt_path")]
+    pub jwt_path: String,
+}
+
+// The JWT is read from `path` or from the environment variable `env`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JwtAuth {
+    pub role: Option<String>,
+    #[serde(default = "default_jwt_mount")]
+    pub mount: String,
+    pub path: Option<String>,
+    pub env: Option<String>,
+}
+
+// Client certificate and key in PEM format, the key must be PKCS#8
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CertAuth {
+    pub cert: String,
+    pub key: String,
+    // Name of the certificate role
+    pub name: Option<String>,
+    #[serde(default = "default_cert_mount")]
+    pub mount: String,
 }
 
-#[derive(Serialize_repr, Deserialize_repr, PartialEq, Clone, Debug)]
+// The password is set in `password`, or read from the environment variable `password_env` or from
+// the file `password_file`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PasswordAuth {
+    pub username: String,
+    #[serde(default, serialize_with = "sanitize_option")]
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<String>,
+    // Default is "userpass" or "ldap"
+    pub mount: Option<String>,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, PartialEq, Clone, Debug, Default)]
 #[repr(u8)]
 pub enum EngineVersion {
     V1 = 1,
+    #[default]
     V2 = 2,
 }
 
@@ -36,6 +117,35 @@ pub struct VaultHost {
     pub auth: Option<VaultAuthMethod>,
     pub token_ttl: Option<u64>,
     pub token_max_ttl: Option<u64>,
+    pub tls: Option<VaultTls>,
+    // Timeout in seconds for the whole request, default is 30
+    pub timeout: Option<u64>,
+    // Timeout in seconds to connect, not set by default
+    pub connect_timeout: Option<u64>,
+    // Proxy URL, by default the proxy is set from HTTP_PROXY, HTTPS_PROXY and NO_PROXY
+    pub proxy: Option<String>,
+    // Comma-separated list of hosts or networks to connect without the proxy
+    pub no_proxy: Option<String>,
+    // Time in seconds to keep idle connections, default is 90
+    pub pool_idle_timeout: Option<u64>,
+    // Maximum number of idle connections, not limited by default
+    pub pool_max_idle: Option<usize>,
+}
+
+// TLS configuration for the connections to Vault, in addition to SSL_CERT_FILE and SSL_CERT_DIR
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VaultTls {
+    // CA certificates in PEM format
+    pub ca_cert: Option<String>,
+    // Directory with CA certificates in PEM format
+    pub ca_path: Option<String>,
+    // Client certificate and key in PEM format, the key must be PKCS#8
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    // Name to use for SNI and to verify the server certificate, instead of the host in the URL
+    pub server_name: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -57,6 +167,8 @@ pub struct VaultSource {
     #[serde(default)]
     pub version: EngineVersion,
     pub namespace: Option<String>,
+    // Child namespaces (relative to `namespace`) mapped to the destination namespaces
+    pub child_namespaces: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -70,23 +182,141 @@ pub struct VaultDestination {
     #[serde(default)]
     pub version: EngineVersion,
     pub namespace: Option<String>,
+    #[serde(default)]
+    pub patch: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditFile {
+    pub path: String,
+    pub checkpoint: Option<String>,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BindTls {
+    pub cert: String,
+    pub key: String,
+    pub client_ca: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditDevice {
+    #[serde(default)]
+    pub manage: bool,
+    pub address: Option<String>,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    #[serde(default)]
+    pub disable_on_shutdown: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Admin {
+    pub bind: String,
+    // Token for the admin API, the API is disabled if the token is not set
+    #[serde(default, serialize_with = "sanitize_option")]
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderElection {
+    pub path: String,
+    pub backend: Option<String>,
+    #[serde(default = "default_lease_duration")]
+    pub lease_duration: u64,
+    pub identity: Option<String>,
+    pub advertise: Option<String>,
+    // CA certificate to verify the leader's bind_tls certificate, default is the system CAs
+    pub ca_cert: Option<String>,
+}
+
+// Allowed hours for the full sync or for deleting secrets
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MaintenanceWindows {
+    // "HH:MM-HH:MM" in UTC
+    pub hours: Vec<String>,
+    #[serde(default = "default_true")]
+    pub full_sync: bool,
+    #[serde(default)]
+    pub delete: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct VaultSyncConfig {
     pub id: String,
     pub full_sync_interval: u64,
+    pub full_sync_schedule: Option<String>,
+    #[serde(default)]
+    pub full_sync_splay: u64,
+    pub maintenance_windows: Option<MaintenanceWindows>,
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
     pub bind: Option<String>,
+    pub bind_tls: Option<BindTls>,
+    pub bind_allow: Option<Vec<String>>,
+    pub audit_device: Option<AuditDevice>,
+    pub audit_file: Option<AuditFile>,
+    pub leader_election: Option<LeaderElection>,
+    pub admin: Option<Admin>,
     pub src: VaultSource,
     pub dst: VaultDestination,
 }
 
+// Configuration shared by the workers, replaced when the configuration is reloaded
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<VaultSyncConfig>>>);
+
+impl SharedConfig {
+    pub fn new(config: VaultSyncConfig) -> SharedConfig {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<VaultSyncConfig> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, config: VaultSyncConfig) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
+// Address to listen for the Vault audit log on, parsed from `bind`
+#[derive(PartialEq, Clone, Debug)]
+pub enum BindAddress {
+    // "host:port" or "tcp://host:port"
+    Tcp(String),
+    // "udp://host:port"
+    Udp(String),
+    // "unix:///path/to/socket"
+    Unix(String),
+}
+
 #[derive(Debug, Clone)]
 pub enum ConfigError {
     AuthRequired,
     OneToManyNotSupported,
     ManyToOneNotSupported,
     DifferentNumberOfBackends,
+    UnsupportedBindAddress(String),
+    InvalidPeer(String),
+    TlsNotSupported,
+    PeerFilterNotSupported,
+    AuditDeviceRequiresBind,
+    AuditDeviceRequiresAddress,
+    AuditDeviceFormatNotSupported(String),
+    PatchNotSupported,
+    LeaderElectionRequiresBackend,
+    LeaderElectionRequiresAdvertise,
+    LeaderForwardingNotSupported,
+    JwtSourceRequired,
+    PasswordSourceRequired,
+    RoleIdSourceRequired,
+    ClientCertRequiresKey,
+    ClientCertWithCertAuth,
+    InvalidFullSyncSchedule(String, String),
+    InvalidMaintenanceWindow(String),
 }
 
 // Returns backend or backends as a vector.
@@ -98,12 +328,55 @@ pub fn get_backends(backend: &Option<Backend>) -> Vec<String> {
     }
 }
 
-impl Default for EngineVersion {
-    fn default() -> Self {
-        EngineVersion::V2
+pub fn parse_bind(bind: &str) -> Result<BindAddress, ConfigError> {
+    match bind.split_once("://") {
+        None => Ok(BindAddress::Tcp(bind.into())),
+        Some(("tcp", addr)) if !addr.is_empty() => Ok(BindAddress::Tcp(addr.into())),
+        Some(("udp", addr)) if !addr.is_empty() => Ok(BindAddress::Udp(addr.into())),
+        Some(("unix", path)) if !path.is_empty() => Ok(BindAddress::Unix(path.into())),
+        _ => Err(ConfigError::UnsupportedBindAddress(bind.into())),
     }
 }
 
+// True if the host of "host:port" is empty or an unspecified address, like 0.0.0.0 or [::]
+fn is_unspecified_address(addr: &str) -> bool {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.is_empty() || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified())
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_lease_duration() -> u64 {
+    30
+}
+
+fn default_shutdown_timeout() -> u64 {
+    25
+}
+
+fn default_kubernetes_mount() -> String {
+    "kubernetes".into()
+}
+
+fn default_kubernetes_jwt_path() -> String {
+    "/var/run/secrets/kubernetes.io/serviceaccount/token".into()
+}
+
+fn default_jwt_mount() -> String {
+    "jwt".into()
+}
+
+fn default_cert_mount() -> String {
+    "cert".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
 impl VaultSyncConfig {
     pub fn from_file(file_name: &str) -> Result<VaultSyncConfig, Box<dyn Error>> {
         let file = File::open(file_name)?;
@@ -114,6 +387,11 @@ impl VaultSyncConfig {
         Ok(config)
     }
 
+    // True if vault-sync enables the audit device by itself
+    pub fn manage_audit_device(&self) -> bool {
+        self.audit_device.as_ref().is_some_and(|audit_device| audit_device.manage)
+    }
+
     fn auth_from_env(&mut self) -> Result<(), Box<dyn Error>> {
         if self.src.host.auth.is_none() {
             self.src.host.auth = Some(VaultAuthMethod::from_env("VAULT_SYNC_SRC")?);
@@ -121,6 +399,11 @@ impl VaultSyncConfig {
         if self.dst.host.auth.is_none() {
             self.dst.host.auth = Some(VaultAuthMethod::from_env("VAULT_SYNC_DST")?);
         }
+        if let Some(admin) = &mut self.admin {
+            if admin.token.is_none() {
+                admin.token = env::var("VAULT_SYNC_ADMIN_TOKEN").ok();
+            }
+        }
         Ok(())
     }
 
@@ -134,16 +417,15 @@ impl VaultSyncConfig {
         Ok(())
     }
 
-    fn validate(&self) -> Result<(), Box<dyn Error>> {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
         let src_backend = self.src.backend.as_ref().unwrap();
         let dst_backend = self.dst.backend.as_ref().unwrap();
 
         match &src_backend {
-            Backend::Backend(_) => match &dst_backend {
-                Backend::Backends(_) => {
+            Backend::Backend(_) => {
+                if let Backend::Backends(_) = &dst_backend {
                     return Err(ConfigError::OneToManyNotSupported.into());
-                },
-                _ => {},
+                }
             },
             Backend::Backends(src_backends) => match &dst_backend {
                 Backend::Backend(_) => {
@@ -156,6 +438,90 @@ impl VaultSyncConfig {
                 }
             }
         }
+        if let Some(bind) = &self.bind {
+            let bind = parse_bind(bind)?;
+            if self.bind_tls.is_some() && !matches!(bind, BindAddress::Tcp(_)) {
+                return Err(ConfigError::TlsNotSupported.into());
+            }
+            if self.bind_allow.is_some() && matches!(bind, BindAddress::Unix(_)) {
+                return Err(ConfigError::PeerFilterNotSupported.into());
+            }
+        }
+        if self.dst.patch && self.dst.version != EngineVersion::V2 {
+            return Err(ConfigError::PatchNotSupported.into());
+        }
+        if let Some(leader_election) = &self.leader_election {
+            // The lock is a KV v2 secret, by default in the first destination backend
+            if leader_election.backend.is_none() && self.dst.version != EngineVersion::V2 {
+                return Err(ConfigError::LeaderElectionRequiresBackend.into());
+            }
+            // The other instances forward the changes to the leader's listener, over TLS with bind_tls
+            if leader_election.advertise.is_none() || self.bind.is_none() {
+                return Err(ConfigError::LeaderElectionRequiresAdvertise.into());
+            }
+            if !matches!(parse_bind(self.bind.as_ref().unwrap())?, BindAddress::Tcp(_)) {
+                return Err(ConfigError::LeaderForwardingNotSupported.into());
+            }
+        }
+        if self.manage_audit_device() && self.bind.is_none() {
+            return Err(ConfigError::AuditDeviceRequiresBind.into());
+        }
+        if let Some(audit_device) = self.audit_device.as_ref().filter(|audit_device| audit_device.manage) {
+            // Vault cannot connect to the wildcard bind address
+            let wildcard = match parse_bind(self.bind.as_ref().unwrap())? {
+                BindAddress::Tcp(addr) | BindAddress::Udp(addr) => is_unspecified_address(&addr),
+                BindAddress::Unix(_) => false,
+            };
+            if wildcard && audit_device.address.is_none() {
+                return Err(ConfigError::AuditDeviceRequiresAddress.into());
+            }
+            // Only JSON audit log entries can be parsed
+            match audit_device.options.get("format") {
+                Some(format) if format != "json" => {
+                    return Err(ConfigError::AuditDeviceFormatNotSupported(format.clone()).into());
+                },
+                _ => {},
+            }
+        }
+        if let Some(peers) = &self.bind_allow {
+            for peer in peers {
+                parse_peer(peer)?;
+            }
+        }
+        for host in [&self.src.host, &self.dst.host] {
+            if let Some(tls) = &host.tls {
+                if tls.client_cert.is_some() != tls.client_key.is_some() {
+                    return Err(ConfigError::ClientCertRequiresKey.into());
+                }
+                // The client presents one certificate, the certificate of Cert auth method
+                if tls.client_cert.is_some() && matches!(host.auth, Some(VaultAuthMethod::CertAuth { .. })) {
+                    return Err(ConfigError::ClientCertWithCertAuth.into());
+                }
+            }
+            match &host.auth {
+                Some(VaultAuthMethod::JwtAuth { jwt }) if jwt.path.is_some() == jwt.env.is_some() => {
+                    return Err(ConfigError::JwtSourceRequired.into());
+                },
+                Some(VaultAuthMethod::AppRoleFileAuth { role_id, role_id_file, .. }) if role_id.is_some() == role_id_file.is_some() => {
+                    return Err(ConfigError::RoleIdSourceRequired.into());
+                },
+                Some(VaultAuthMethod::UserpassAuth { userpass: auth }) | Some(VaultAuthMethod::LdapAuth { ldap: auth }) => {
+                    let sources = [&auth.password, &auth.password_env, &auth.password_file];
+                    if sources.iter().filter(|source| source.is_some()).count() != 1 {
+                        return Err(ConfigError::PasswordSourceRequired.into());
+                    }
+                },
+                _ => {},
+            }
+        }
+        if let Some(schedule) = &self.full_sync_schedule {
+            schedule::parse_schedule(schedule)?;
+        }
+        if let Some(windows) = &self.maintenance_windows {
+            for window in &windows.hours {
+                window.parse::<TimeWindow>()?;
+            }
+        }
         Ok(())
     }
 }
@@ -165,27 +531,104 @@ impl VaultAuthMethod {
         let token = env::var(format!("{}_TOKEN", prefix));
         let role_id = env::var(format!("{}_ROLE_ID", prefix));
         let secret_id = env::var(format!("{}_SECRET_ID", prefix));
+        let secret_id_wrapped = env::var(format!("{}_SECRET_ID_WRAPPED", prefix)).is_ok_and(|wrapped| wrapped == "true");
         if let Ok(token) = token {
             return Ok(VaultAuthMethod::TokenAuth { token })
         }
-        if let (Ok(role_id), Ok(secret_id)) = (role_id, secret_id) {
-            return Ok(VaultAuthMethod::AppRoleAuth { role_id, secret_id })
+        if let (Ok(role_id), Ok(secret_id)) = (&role_id, secret_id) {
+            return Ok(VaultAuthMethod::AppRoleAuth { role_id: role_id.clone(), secret_id, secret_id_wrapped })
+        }
+        if let Ok(token_file) = env::var(format!("{}_TOKEN_FILE", prefix)) {
+            return Ok(VaultAuthMethod::TokenFileAuth { token_file })
+        }
+        if let Ok(secret_id_file) = env::var(format!("{}_SECRET_ID_FILE", prefix)) {
+            return Ok(VaultAuthMethod::AppRoleFileAuth {
+                role_id: role_id.ok(),
+                role_id_file: env::var(format!("{}_ROLE_ID_FILE", prefix)).ok(),
+                secret_id_file,
+                secret_id_wrapped,
+            })
+        }
+        if let Ok(role) = env::var(format!("{}_KUBERNETES_ROLE", prefix)) {
+            return Ok(VaultAuthMethod::KubernetesAuth {
+                kubernetes: KubernetesAuth {
+                    role,
+                    mount: default_kubernetes_mount(),
+                    jwt_path: default_kubernetes_jwt_path(),
+                },
+            })
         }
         Err(ConfigError::AuthRequired.into())
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VaultAuthMethod::TokenAuth { .. } => "Token",
+            VaultAuthMethod::AppRoleAuth { .. } => "AppRole",
+            VaultAuthMethod::TokenFileAuth { .. } => "Token file",
+            VaultAuthMethod::AppRoleFileAuth { .. } => "AppRole",
+            VaultAuthMethod::KubernetesAuth { .. } => "Kubernetes",
+            VaultAuthMethod::JwtAuth { .. } => "JWT",
+            VaultAuthMethod::CertAuth { .. } => "Cert",
+            VaultAuthMethod::UserpassAuth { .. } => "Userpass",
+            VaultAuthMethod::LdapAuth { .. } => "LDAP",
+        }
+    }
+
+    // True if vault-sync can log in again to get a new token, when the current token reaches its
+    // max TTL or is revoked
+    pub fn can_login(&self) -> bool {
+        !matches!(self, VaultAuthMethod::TokenAuth { .. })
+    }
 }
 
 impl fmt::Display for ConfigError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             ConfigError::AuthRequired =>
-                write!(f, "Vault token or both app role id and secret id are required"),
+                write!(f, "Vault token, both app role id and secret id, or Kubernetes role are required"),
             ConfigError::OneToManyNotSupported =>
                 write!(f, "Syncing one backend to many not supported"),
             ConfigError::ManyToOneNotSupported =>
                 write!(f, "Syncing many backends to one not supported"),
             ConfigError::DifferentNumberOfBackends =>
                 write!(f, "Different number of backends for source and destination"),
+            ConfigError::UnsupportedBindAddress(ref bind) =>
+                write!(f, "Unsupported bind address {}, expected host:port, tcp://host:port, udp://host:port or unix:///path", bind),
+            ConfigError::InvalidPeer(ref peer) =>
+                write!(f, "Invalid peer address {}, expected IP address or network in CIDR notation", peer),
+            ConfigError::TlsNotSupported =>
+                write!(f, "TLS is supported only for tcp bind address"),
+            ConfigError::PeerFilterNotSupported =>
+                write!(f, "Allowed peers are not supported for unix bind address"),
+            ConfigError::AuditDeviceRequiresBind =>
+                write!(f, "Managing the audit device requires bind address"),
+            ConfigError::AuditDeviceRequiresAddress =>
+                write!(f, "Managing the audit device requires audit_device.address when bind address is a wildcard address"),
+            ConfigError::AuditDeviceFormatNotSupported(ref format) =>
+                write!(f, "Unsupported audit device format {}, only json is supported", format),
+            ConfigError::PatchNotSupported =>
+                write!(f, "Patching secrets is supported only for KV secrets engine v2"),
+            ConfigError::LeaderElectionRequiresBackend =>
+                write!(f, "Leader election requires KV secrets engine v2 backend for the lock"),
+            ConfigError::LeaderElectionRequiresAdvertise =>
+                write!(f, "Leader election requires bind address and leader_election.advertise"),
+            ConfigError::LeaderForwardingNotSupported =>
+                write!(f, "Leader election requires tcp bind address"),
+            ConfigError::JwtSourceRequired =>
+                write!(f, "Either jwt.path or jwt.env is required for JWT auth method"),
+            ConfigError::PasswordSourceRequired =>
+                write!(f, "Exactly one of password, password_env or password_file is required for Userpass and LDAP auth methods"),
+            ConfigError::RoleIdSourceRequired =>
+                write!(f, "Exactly one of role_id or role_id_file is required with secret_id_file"),
+            ConfigError::ClientCertRequiresKey =>
+                write!(f, "Both tls.client_cert and tls.client_key are required"),
+            ConfigError::ClientCertWithCertAuth =>
+                write!(f, "tls.client_cert cannot be used with Cert auth method, which presents its own certificate"),
+            ConfigError::InvalidFullSyncSchedule(ref schedule, ref error) =>
+                write!(f, "Invalid full sync schedule {}: {}", schedule, error),
+            ConfigError::InvalidMaintenanceWindow(ref window) =>
+                write!(f, "Invalid maintenance window {}, expected HH:MM-HH:MM", window),
         }
     }
 }
@@ -200,10 +643,20 @@ where
     s.serialize_str("***")
 }
 
+fn sanitize_option<S>(value: &Option<String>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(_) => s.serialize_str("***"),
+        None => s.serialize_none(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
-    use crate::config::{EngineVersion, VaultSyncConfig, get_backends, ConfigError};
+    use crate::config::{EngineVersion, CertAuth, VaultAuthMethod, VaultHost, VaultSyncConfig, get_backends, parse_bind, BindAddress, ConfigError};
 
     #[test]
     fn test_load() -> Result<(), Box<dyn Error>> {
@@ -225,6 +678,70 @@ mod tests {
         assert_eq!(config.bind, Some("0.0.0.0:8202".to_string()));
         assert_eq!(config.src.version, EngineVersion::V2);
         assert_eq!(config.dst.version, EngineVersion::V1);
+        assert!(config.audit_file.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_audit_file() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            audit_file:
+              path: /var/log/vault/audit.log
+            src:
+              url: http://127.0.0.1:8200/
+            dst:
+              url: http://127.0.0.1:8200/
+        "#;
+        let config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        let audit_file = config.audit_file.unwrap();
+        assert_eq!(audit_file.path, "/var/log/vault/audit.log");
+        assert_eq!(audit_file.checkpoint, None);
+        assert_eq!(audit_file.poll_interval_ms, 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_auth() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            src:
+              url: http://127.0.0.1:8200/
+              token: src-token
+            dst:
+              url: http://127.0.0.1:8200/
+              kubernetes:
+                role: vault-sync
+        "#;
+        let config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        assert_eq!(config.src.host.auth, Some(VaultAuthMethod::TokenAuth { token: "src-token".into() }));
+        let dst_auth = config.dst.host.auth.unwrap();
+        assert!(dst_auth.can_login());
+        match dst_auth {
+            VaultAuthMethod::KubernetesAuth { kubernetes } => {
+                assert_eq!(kubernetes.role, "vault-sync");
+                assert_eq!(kubernetes.mount, "kubernetes");
+                assert_eq!(kubernetes.jwt_path, "/var/run/secrets/kubernetes.io/serviceaccount/token");
+            },
+            auth => panic!("Unexpected auth method {}", auth.name()),
+        }
+
+        let yaml = r#"
+            url: http://127.0.0.1:8200/
+            cert:
+              cert: client.crt
+              key: client.key
+        "#;
+        let host: VaultHost = serde_yaml::from_str(yaml)?;
+        match host.auth.unwrap() {
+            VaultAuthMethod::CertAuth { cert } => {
+                assert_eq!(cert.mount, "cert");
+                assert_eq!(cert.name, None);
+            },
+            auth => panic!("Unexpected auth method {}", auth.name()),
+        }
         Ok(())
     }
 
@@ -331,4 +848,255 @@ mod tests {
         assert_eq!(result.unwrap_err().to_string(), ConfigError::DifferentNumberOfBackends.to_string());
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_bind() {
+        assert_eq!(parse_bind("0.0.0.0:8202").unwrap(), BindAddress::Tcp("0.0.0.0:8202".into()));
+        assert_eq!(parse_bind("tcp://0.0.0.0:8202").unwrap(), BindAddress::Tcp("0.0.0.0:8202".into()));
+        assert_eq!(parse_bind("udp://0.0.0.0:8202").unwrap(), BindAddress::Udp("0.0.0.0:8202".into()));
+        assert_eq!(parse_bind("unix:///run/vault-sync.sock").unwrap(), BindAddress::Unix("/run/vault-sync.sock".into()));
+        assert!(parse_bind("http://0.0.0.0:8202").is_err());
+        assert!(parse_bind("unix://").is_err());
+    }
+
+    #[test]
+    fn test_bind_tls_requires_tcp() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            bind: unix:///run/vault-sync.sock
+            bind_tls:
+              cert: server.crt
+              key: server.key
+            src:
+              url: http://127.0.0.1:8200/
+            dst:
+              url: http://127.0.0.1:8200/
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::TlsNotSupported.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_allow() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            bind: 0.0.0.0:8202
+            bind_allow: [10.0.0.0/8, 10.0.0.300]
+            src:
+              url: http://127.0.0.1:8200/
+            dst:
+              url: http://127.0.0.1:8200/
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::InvalidPeer("10.0.0.300".into()).to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_device() -> Result<(), Box<dyn Error>> {
+        let validate = |bind: &str, audit_device: &str| -> Result<(), Box<dyn Error>> {
+            let yaml = format!(
+                r#"
+                    id: vault-sync-id
+                    full_sync_interval: 60
+                    bind: {}
+                    audit_device:
+                      manage: true
+                      {}
+                    src:
+                      url: http://127.0.0.1:8200/
+                    dst:
+                      url: http://127.0.0.1:8200/
+                "#,
+                bind,
+                audit_device,
+            );
+            let mut config: VaultSyncConfig = serde_yaml::from_str(&yaml)?;
+            config.defaults()?;
+            config.validate()
+        };
+        let required = ConfigError::AuditDeviceRequiresAddress.to_string();
+        assert_eq!(validate("0.0.0.0:8202", "").unwrap_err().to_string(), required);
+        assert_eq!(validate("udp://[::]:8202", "").unwrap_err().to_string(), required);
+        assert_eq!(validate(":8202", "").unwrap_err().to_string(), required);
+        assert!(validate("0.0.0.0:8202", "address: vault-sync:8202").is_ok());
+        assert!(validate("127.0.0.1:8202", "").is_ok());
+        assert!(validate("unix:///run/vault-sync.sock", "").is_ok());
+
+        let options = "options: {format: jsonx}";
+        assert_eq!(
+            validate("127.0.0.1:8202", options).unwrap_err().to_string(),
+            ConfigError::AuditDeviceFormatNotSupported("jsonx".into()).to_string(),
+        );
+        assert!(validate("127.0.0.1:8202", "options: {format: json}").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_leader_election() -> Result<(), Box<dyn Error>> {
+        let validate = |listener: &str, advertise: &str| -> Result<(), Box<dyn Error>> {
+            let yaml = format!(
+                r#"
+                    id: vault-sync-id
+                    full_sync_interval: 60
+                    {}
+                    leader_election:
+                      path: vault-sync/leader
+                      {}
+                    src:
+                      url: http://127.0.0.1:8200/
+                    dst:
+                      url: http://127.0.0.1:8200/
+                "#,
+                listener,
+                advertise,
+            );
+            let mut config: VaultSyncConfig = serde_yaml::from_str(&yaml)?;
+            config.defaults()?;
+            config.validate()
+        };
+        let advertise = "advertise: vault-sync-0:8202";
+        assert!(validate("bind: 0.0.0.0:8202", advertise).is_ok());
+
+        let required = ConfigError::LeaderElectionRequiresAdvertise.to_string();
+        assert_eq!(validate("bind: 0.0.0.0:8202", "").unwrap_err().to_string(), required);
+        assert_eq!(validate("", advertise).unwrap_err().to_string(), required);
+
+        let not_supported = ConfigError::LeaderForwardingNotSupported.to_string();
+        assert_eq!(validate("bind: udp://0.0.0.0:8202", advertise).unwrap_err().to_string(), not_supported);
+        assert_eq!(validate("bind: unix:///run/vault-sync.sock", advertise).unwrap_err().to_string(), not_supported);
+        let bind_allow = "bind: 0.0.0.0:8202\n                    bind_allow: [10.0.0.0/8]";
+        assert!(validate(bind_allow, advertise).is_ok());
+        let bind_tls = "bind: 0.0.0.0:8202\n                    bind_tls: {cert: server.crt, key: server.key}";
+        assert!(validate(bind_tls, advertise).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_jwt_source_required() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            src:
+              url: http://127.0.0.1:8200/
+              jwt:
+                role: ci
+                path: /run/secrets/jwt
+            dst:
+              url: http://127.0.0.1:8200/
+              jwt:
+                role: ci
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        assert_eq!(config.src.host.auth.as_ref().map(|auth| auth.name()), Some("JWT"));
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::JwtSourceRequired.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_source_required() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            src:
+              url: http://127.0.0.1:8200/
+              userpass:
+                username: vault-sync
+                password_file: /run/secrets/password
+            dst:
+              url: http://127.0.0.1:8200/
+              ldap:
+                username: vault-sync
+                password: secret
+                password_env: VAULT_SYNC_DST_PASSWORD
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        assert_eq!(config.src.host.auth.as_ref().map(|auth| auth.name()), Some("Userpass"));
+        assert_eq!(config.dst.host.auth.as_ref().map(|auth| auth.name()), Some("LDAP"));
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::PasswordSourceRequired.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential_files() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            src:
+              url: http://127.0.0.1:8200/
+              token_file: /vault/token
+            dst:
+              url: http://127.0.0.1:8200/
+              role_id: dst-role-id
+              secret_id_file: /vault/secret-id
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        assert_eq!(config.src.host.auth, Some(VaultAuthMethod::TokenFileAuth { token_file: "/vault/token".into() }));
+        assert_eq!(config.dst.host.auth, Some(VaultAuthMethod::AppRoleFileAuth {
+            role_id: Some("dst-role-id".into()),
+            role_id_file: None,
+            secret_id_file: "/vault/secret-id".into(),
+            secret_id_wrapped: false,
+        }));
+        config.validate()?;
+        if let Some(VaultAuthMethod::AppRoleFileAuth { role_id_file, .. }) = &mut config.dst.host.auth {
+            *role_id_file = Some("/vault/role-id".into());
+        }
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::RoleIdSourceRequired.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls() -> Result<(), Box<dyn Error>> {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            src:
+              url: https://127.0.0.1:8200/
+              token: src-token
+              tls:
+                ca_cert: /etc/vault-sync/src-ca.crt
+                server_name: vault.example.com
+            dst:
+              url: https://127.0.0.1:8200/
+              token: dst-token
+              tls:
+                client_cert: /etc/vault-sync/client.crt
+        "#;
+        let mut config: VaultSyncConfig = serde_yaml::from_str(yaml)?;
+        config.defaults()?;
+        let src_tls = config.src.host.tls.as_ref().unwrap();
+        assert_eq!(src_tls.server_name.as_deref(), Some("vault.example.com"));
+        assert!(!src_tls.insecure_skip_verify);
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::ClientCertRequiresKey.to_string());
+
+        let dst_tls = config.dst.host.tls.as_mut().unwrap();
+        dst_tls.client_key = Some("/etc/vault-sync/client.key".into());
+        assert!(config.validate().is_ok());
+        config.dst.host.auth = Some(VaultAuthMethod::CertAuth {
+            cert: CertAuth {
+                cert: "/etc/vault-sync/cert.crt".into(),
+                key: "/etc/vault-sync/cert.key".into(),
+                name: None,
+                mount: "cert".into(),
+            },
+        });
+        let result = config.validate();
+        assert_eq!(result.unwrap_err().to_string(), ConfigError::ClientCertWithCertAuth.to_string());
+        Ok(())
+    }
+}
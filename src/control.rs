@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+
+// Results of the last full sync, reported by the admin API
+#[derive(Serialize, Clone, Debug)]
+pub struct FullSyncResult {
+    pub finished: String,
+    pub updated: u64,
+    pub deleted: u64,
+}
+
+// State of the sync worker, controlled by the admin API
+pub struct SyncControl {
+    paused: AtomicBool,
+    last_full_sync: Mutex<Option<FullSyncResult>>,
+}
+
+pub static CONTROL: SyncControl = SyncControl::new();
+
+impl SyncControl {
+    pub const fn new() -> SyncControl {
+        SyncControl {
+            paused: AtomicBool::new(false),
+            last_full_sync: Mutex::new(None),
+        }
+    }
+
+    // While paused, the sync worker does not sync secrets, but the secret operations are still
+    // queued
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn set_last_full_sync(&self, updated: u64, deleted: u64) {
+        *self.last_full_sync.lock().unwrap() = Some(FullSyncResult {
+            finished: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            updated,
+            deleted,
+        });
+    }
+
+    pub fn last_full_sync(&self) -> Option<FullSyncResult> {
+        self.last_full_sync.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control::SyncControl;
+
+    #[test]
+    fn test_sync_control() {
+        static CONTROL: SyncControl = SyncControl::new();
+        assert!(!CONTROL.paused());
+        assert!(CONTROL.last_full_sync().is_none());
+        CONTROL.set_paused(true);
+        assert!(CONTROL.paused());
+        CONTROL.set_last_full_sync(3, 1);
+        let last = CONTROL.last_full_sync().unwrap();
+        assert_eq!((last.updated, last.deleted), (3, 1));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use log::error;
+
+use crate::shutdown;
+
+// Health of this vault-sync instance, reported by the admin endpoints
+pub struct Health {
+    // Worker name -> false if the worker died
+    workers: Mutex<BTreeMap<String, bool>>,
+    // Vault side ("src" or "dst") -> false if the last token renewal failed
+    tokens: Mutex<BTreeMap<String, bool>>,
+    full_sync_finished: AtomicBool,
+    standby: AtomicBool,
+}
+
+pub static HEALTH: Health = Health::new();
+
+impl Health {
+    pub const fn new() -> Health {
+        Health {
+            workers: Mutex::new(BTreeMap::new()),
+            tokens: Mutex::new(BTreeMap::new()),
+            full_sync_finished: AtomicBool::new(false),
+            standby: AtomicBool::new(false),
+        }
+    }
+
+    // Registers a worker thread, the returned guard marks the worker as dead if the thread panics
+    pub fn worker(&'static self, name: &str) -> WorkerGuard {
+        self.workers.lock().unwrap().insert(name.to_string(), true);
+        WorkerGuard { health: self, name: name.to_string() }
+    }
+
+    pub fn set_token(&self, side: &str, ok: bool) {
+        self.tokens.lock().unwrap().insert(side.to_string(), ok);
+    }
+
+    pub fn set_full_sync_finished(&self) {
+        self.full_sync_finished.store(true, Ordering::SeqCst);
+    }
+
+    // Standby instances (not the leader) do not run the full sync
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::SeqCst);
+    }
+
+    // Returns the list of problems, empty if the instance is healthy
+    pub fn health_problems(&self) -> Vec<String> {
+        self.workers.lock().unwrap().iter()
+            .filter(|(_, alive)| !**alive)
+            .map(|(name, _)| format!("worker {} died", name))
+            .collect()
+    }
+
+    // Returns the list of problems, empty if the instance is ready
+    pub fn ready_problems(&self) -> Vec<String> {
+        let mut problems = self.health_problems();
+        if shutdown::requested() {
+            problems.push("shutting down".to_string());
+        }
+        let tokens = self.tokens.lock().unwrap();
+        for side in ["src", "dst"] {
+            match tokens.get(side) {
+                Some(true) => {},
+                Some(false) => problems.push(format!("{} token renewal failed", side)),
+                None => problems.push(format!("{} client is not authenticated", side)),
+            }
+        }
+        if !self.full_sync_finished.load(Ordering::SeqCst) && !self.standby.load(Ordering::SeqCst) {
+            problems.push("first full sync is not finished".to_string());
+        }
+        problems
+    }
+}
+
+pub struct WorkerGuard {
+    health: &'static Health,
+    name: String,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        // Workers that return normally, such as the token worker for a non-renewable token, are
+        // not considered dead
+        if thread::panicking() {
+            error!(worker = self.name.as_str(); "Worker died");
+            self.health.workers.lock().unwrap().insert(self.name.clone(), false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::health::Health;
+
+    #[test]
+    fn test_health() {
+        static HEALTH: Health = Health::new();
+        assert_eq!(HEALTH.ready_problems().len(), 3);
+        HEALTH.set_token("src", true);
+        HEALTH.set_token("dst", false);
+        HEALTH.set_full_sync_finished();
+        assert_eq!(HEALTH.ready_problems(), vec!["dst token renewal failed"]);
+        HEALTH.set_token("dst", true);
+        assert!(HEALTH.ready_problems().is_empty());
+
+        let _ = thread::spawn(|| {
+            let _guard = HEALTH.worker("ok");
+        }).join();
+        assert!(HEALTH.health_problems().is_empty());
+        let _ = thread::spawn(|| {
+            let _guard = HEALTH.worker("sync");
+            panic!("test");
+        }).join();
+        assert_eq!(HEALTH.health_problems(), vec!["worker sync died"]);
+        assert_eq!(HEALTH.ready_problems(), vec!["worker sync died"]);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hashicorp_vault::client::error::Error as VaultError;
+use hashicorp_vault::client::{EndpointResponse, HttpVerb};
+use log::{info, warn};
+use openssl::ssl::SslConnector;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{get_backends, EngineVersion, LeaderElection, VaultSource, VaultSyncConfig};
+use crate::health::HEALTH;
+use crate::listener;
+use crate::sync::{src_namespace, SecretOp};
+use crate::vault::{retry_on_timeout, VaultClient};
+
+// Secret operations queued for the leader while it is unknown or unreachable
+const MAX_PENDING: usize = 10000;
+// Timeout to connect and send the secret operations to the leader
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Content of the lock secret in the destination Vault
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Lock {
+    holder: String,
+    address: Option<String>,
+    // Unix time (seconds) when the lock expires, if it is not renewed by the holder
+    expires: u64,
+}
+
+// State of the leader election for this instance. Only the leader syncs secrets, the other
+// instances forward the secret operations to the leader.
+pub struct Leader {
+    election: LeaderElection,
+    identity: String,
+    backend: String,
+    namespace: Option<String>,
+    src: VaultSource,
+    is_leader: AtomicBool,
+    lock: Mutex<Option<Lock>>,
+    // Audit log entries to send to the leader
+    pending: Mutex<VecDeque<String>>,
+    forward_stream: Mutex<Option<(String, Box<dyn Write + Send>)>>,
+    // The leader's listener uses TLS
+    connector: Option<SslConnector>,
+}
+
+impl Leader {
+    pub fn new(config: &VaultSyncConfig) -> Result<Leader, Box<dyn Error>> {
+        let election = config.leader_election.clone().unwrap();
+        let identity = election.identity.clone()
+            .or_else(|| env::var("HOSTNAME").ok())
+            .unwrap_or_else(|| format!("{}-{}", &config.id, std::process::id()));
+        let backend = election.backend.clone()
+            .unwrap_or_else(|| get_backends(&config.dst.backend)[0].clone());
+        let connector = match &config.bind_tls {
+            Some(tls) => Some(listener::tls_connector(tls, &election.ca_cert)?),
+            None => None,
+        };
+        Ok(Leader {
+            election,
+            identity,
+            backend,
+            namespace: config.dst.namespace.clone(),
+            src: config.src.clone(),
+            is_leader: AtomicBool::new(false),
+            lock: Mutex::new(None),
+            pending: Mutex::new(VecDeque::new()),
+            forward_stream: Mutex::new(None),
+            connector,
+        })
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+
+    // Blocks until this instance becomes the leader
+    pub fn wait(&self) {
+        while !self.is_leader() {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    // Queues the secret operation for the leader as an audit log entry, then sends the queued
+    // entries. The entries stay queued while the leader is unknown or unreachable.
+    pub fn forward(&self, op: &SecretOp) {
+        let entry = match audit_log_entry(&self.src, op) {
+            Some(entry) => entry,
+            None => return,
+        };
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.len() >= MAX_PENDING {
+                pending.pop_front();
+                warn!(max = MAX_PENDING; "Too many secret operations queued for the leader, dropping the oldest");
+            }
+            pending.push_back(entry);
+        }
+        self.flush();
+    }
+
+    // Sends the queued entries to the leader, the rest stay queued if the leader is unreachable
+    fn flush(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return;
+        }
+        let address = match self.leader_address() {
+            Some(address) => address,
+            None => return,
+        };
+        let mut forward_stream = self.forward_stream.lock().unwrap();
+        let mut connected = false;
+        while let Some(entry) = pending.front() {
+            let mut stream = match forward_stream.take() {
+                Some((stream_address, stream)) if stream_address == address => stream,
+                _ => match self.connect(&address) {
+                    Ok(stream) => {
+                        connected = true;
+                        stream
+                    },
+                    Err(error) => {
+                        warn!(leader = address.as_str(), queued = pending.len(), error:%; "Failed to connect to the leader");
+                        return;
+                    }
+                },
+            };
+            match stream.write_all(entry.as_bytes()) {
+                Ok(()) => {
+                    pending.pop_front();
+                    *forward_stream = Some((address.clone(), stream));
+                },
+                // The connection to the leader was closed, reconnect once
+                Err(_) if !connected => continue,
+                Err(error) => {
+                    warn!(leader = address.as_str(), queued = pending.len(), error:%; "Failed to forward to the leader");
+                    return;
+                }
+            }
+        }
+    }
+
+    // Address of the leader, if it is another instance
+    fn leader_address(&self) -> Option<String> {
+        self.lock.lock().unwrap().as_ref()
+            .filter(|lock| lock.holder != self.identity)
+            .and_then(|lock| lock.address.clone())
+    }
+
+    fn connect(&self, address: &str) -> Result<Box<dyn Write + Send>, Box<dyn Error>> {
+        let addr = address.to_socket_addrs()?.next().ok_or("No address for the leader")?;
+        let stream = TcpStream::connect_timeout(&addr, FORWARD_TIMEOUT)?;
+        stream.set_write_timeout(Some(FORWARD_TIMEOUT))?;
+        match &self.connector {
+            Some(connector) => {
+                stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+                let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let stream = connector.connect(host, stream).map_err(|error| error.to_string())?;
+                Ok(Box::new(stream))
+            },
+            None => Ok(Box::new(stream)),
+        }
+    }
+
+    // Acquires or renews the lock, returns true if this instance holds the lock
+    fn try_lock(&self, client: &mut VaultClient) -> Result<bool, Box<dyn Error>> {
+        let endpoint = format!("{}/data/{}", &self.backend, &self.election.path);
+        client.namespace = self.namespace.clone();
+        let (lock, version) = self.read_lock(client, &endpoint)?;
+        let now = unix_time();
+        if !can_acquire(lock.as_ref(), &self.identity, now) {
+            *self.lock.lock().unwrap() = lock;
+            return Ok(false);
+        }
+        let lock = Lock {
+            holder: self.identity.clone(),
+            address: self.election.advertise.clone(),
+            expires: now + self.election.lease_duration,
+        };
+        let body = json!({
+            "options": {"cas": version},
+            "data": &lock,
+        });
+        // Fails if another instance updated the lock after it was read
+        match client.call_endpoint::<Value>(HttpVerb::PUT, &endpoint, None, Some(&body.to_string())) {
+            Ok(_) => {},
+            // The request may have updated the lock before it timed out, then writing it again
+            // with the same cas version fails, so the lock is read instead
+            Err(error) if error.is_timeout() => {
+                warn!(error:%; "Writing the leader lock timed out, reading it");
+                if self.read_lock(client, &endpoint)?.0.as_ref() != Some(&lock) {
+                    return Err(error.into());
+                }
+            },
+            Err(error) => return Err(error.into()),
+        }
+        *self.lock.lock().unwrap() = Some(lock);
+        Ok(true)
+    }
+
+    // Returns the lock, if any, and the version of the lock secret for the check-and-set
+    fn read_lock(&self, client: &mut VaultClient, endpoint: &str) -> Result<(Option<Lock>, u64), Box<dyn Error>> {
+        match retry_on_timeout(|| client.call_endpoint::<Value>(HttpVerb::GET, endpoint, None, None)) {
+            Ok(EndpointResponse::VaultResponse(response)) => {
+                let data = response.data.unwrap_or_default();
+                let lock: Option<Lock> = serde_json::from_value(data["data"].clone()).ok();
+                Ok((lock, data["metadata"]["version"].as_u64().unwrap_or(0)))
+            },
+            Ok(EndpointResponse::Empty) => Ok((None, 0)),
+            Err(VaultError::VaultResponse(_, response)) if response.status() == 404 => Ok((None, 0)),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+// Worker to acquire and renew the leader lock in the destination Vault
+pub fn leader_election_worker(leader: Arc<Leader>, client: Arc<Mutex<VaultClient>>) {
+    info!(identity = leader.identity.as_str(); "LeaderElection worker started");
+    let interval = Duration::from_secs((leader.election.lease_duration / 3).max(1));
+    loop {
+        let result = {
+            let mut client = client.lock().unwrap();
+            leader.try_lock(&mut client)
+        };
+        let is_leader = match result {
+            Ok(is_leader) => is_leader,
+            Err(error) => {
+                warn!(error:%; "Failed to acquire the leader lock");
+                // The lock may be held by this instance, do not forward to itself
+                *leader.lock.lock().unwrap() = None;
+                false
+            }
+        };
+        HEALTH.set_standby(!is_leader);
+        if is_leader != leader.is_leader.swap(is_leader, Ordering::SeqCst) {
+            if is_leader {
+                // The full sync of the new leader covers the operations queued for the old leader
+                let dropped = leader.pending.lock().unwrap().drain(..).count();
+                info!(dropped; "This instance is the leader");
+            } else {
+                info!("This instance is a standby");
+            }
+        }
+        if !is_leader {
+            leader.flush();
+        }
+        thread::sleep(interval);
+    }
+}
+
+// Returns true if the lock is free, expired or already held by this instance
+fn can_acquire(lock: Option<&Lock>, identity: &str, now: u64) -> bool {
+    match lock {
+        Some(lock) => lock.holder == identity || lock.expires <= now,
+        None => true,
+    }
+}
+
+// Converts the secret operation back to an audit log entry, as the leader expects from the audit
+// device
+fn audit_log_entry(src: &VaultSource, op: &SecretOp) -> Option<String> {
+    let (operation, path) = match op {
+        SecretOp::Create(path) => ("create", path),
+        SecretOp::Update(path) => ("update", path),
+        SecretOp::Delete(path) => ("delete", path),
+        SecretOp::FullSyncFinished | SecretOp::Shutdown => return None,
+    };
+    let request_path = match src.version {
+        EngineVersion::V1 => format!("{}/{}", &path.mount, &path.path),
+        EngineVersion::V2 => format!("{}/data/{}", &path.mount, &path.path),
+    };
+    let namespace = src_namespace(src, &path.namespace)
+        .map(|namespace| format!("{}/", namespace.trim_matches('/')))
+        .unwrap_or_default();
+    let entry = json!({
+        "time": "",
+        "type": "response",
+        "request": {
+            "operation": operation,
+            "mount_type": "kv",
+            "path": request_path,
+            "namespace": {"path": namespace},
+        },
+    });
+    Some(format!("{}\n", entry))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use crate::config::{VaultSource, VaultSyncConfig};
+    use crate::leader::{audit_log_entry, can_acquire, Leader, Lock};
+    use crate::sync::{audit_log_op, AuditFilter, SecretOp, SecretPath};
+
+    #[test]
+    fn test_can_acquire() {
+        let lock = Lock { holder: "vault-sync-0".into(), address: None, expires: 100 };
+        assert!(can_acquire(None, "vault-sync-1", 50));
+        assert!(can_acquire(Some(&lock), "vault-sync-0", 50));
+        assert!(!can_acquire(Some(&lock), "vault-sync-1", 50));
+        assert!(can_acquire(Some(&lock), "vault-sync-1", 100));
+    }
+
+    #[test]
+    fn test_audit_log_entry() {
+        for (version, namespace, child_namespace) in [(1, None, None), (2, None, None), (2, Some("team"), None), (2, Some("team"), Some("app1"))] {
+            let yaml = format!(
+                "url: http://127.0.0.1:8200/\nbackend: secret\nversion: {}\n{}child_namespaces:\n  app1: dst",
+                version,
+                namespace.map_or("".to_string(), |ns| format!("namespace: {}\n", ns)),
+            );
+            let src: VaultSource = serde_yaml::from_str(&yaml).unwrap();
+            let path = SecretPath {
+                mount: "secret".into(),
+                path: "path/to/secret".into(),
+                namespace: child_namespace.map(|ns| ns.to_string()),
+            };
+            let entry = audit_log_entry(&src, &SecretOp::Delete(path.clone())).unwrap();
+            let op = audit_log_op(&AuditFilter::new(&src), &serde_json::from_str(&entry).unwrap());
+            assert!(matches!(op, Some(SecretOp::Delete(op_path)) if op_path == path));
+        }
+    }
+
+    #[test]
+    fn test_forward() {
+        let yaml = r#"
+            id: vault-sync-id
+            full_sync_interval: 60
+            bind: 0.0.0.0:8202
+            leader_election:
+              path: vault-sync/leader
+              identity: vault-sync-0
+              advertise: vault-sync-0:8202
+            src:
+              url: http://127.0.0.1:8200/
+              backend: secret
+            dst:
+              url: http://127.0.0.1:8200/
+              backend: secret
+        "#;
+        let config: VaultSyncConfig = serde_yaml::from_str(yaml).unwrap();
+        let leader = Leader::new(&config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Some(listener.local_addr().unwrap().to_string());
+        let op = SecretOp::Create(SecretPath { mount: "secret".into(), path: "path/to/secret".into(), namespace: None });
+
+        // Queued while the leader is unknown
+        leader.forward(&op);
+        assert_eq!(leader.pending.lock().unwrap().len(), 1);
+
+        // Never forwarded to this instance
+        *leader.lock.lock().unwrap() = Some(Lock { holder: "vault-sync-0".into(), address: address.clone(), expires: 0 });
+        leader.forward(&op);
+        assert_eq!(leader.pending.lock().unwrap().len(), 2);
+
+        *leader.lock.lock().unwrap() = Some(Lock { holder: "vault-sync-1".into(), address, expires: 0 });
+        leader.flush();
+        assert!(leader.pending.lock().unwrap().is_empty());
+        *leader.forward_stream.lock().unwrap() = None;
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut lines = String::new();
+        stream.read_to_string(&mut lines).unwrap();
+        let filter = AuditFilter::new(&config.src);
+        for line in lines.lines() {
+            let op = audit_log_op(&filter, &serde_json::from_str(line).unwrap());
+            assert!(matches!(op, Some(SecretOp::Create(path)) if path.path == "path/to/secret"));
+        }
+        assert_eq!(lines.lines().count(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use ipnet::IpNet;
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslAcceptor, SslConnector, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509Name;
+
+use crate::config::{BindTls, ConfigError};
+
+// Time for a peer to complete the TLS handshake, so a peer that connects and sends nothing does
+// not hold the connection
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Allowlist of peer addresses for the audit log listener
+#[derive(Clone, Debug)]
+pub struct PeerFilter {
+    allowed: Option<Vec<IpNet>>,
+}
+
+impl PeerFilter {
+    pub fn new(allowed: &Option<Vec<String>>) -> Result<PeerFilter, ConfigError> {
+        let allowed = match allowed {
+            Some(allowed) => Some(allowed.iter().map(|s| parse_peer(s)).collect::<Result<_, _>>()?),
+            None => None,
+        };
+        Ok(PeerFilter { allowed })
+    }
+
+    // Returns true if the peer is allowed to send the audit log, all peers are allowed if
+    // the allowlist is not set
+    pub fn allows(&self, peer: &IpAddr) -> bool {
+        match &self.allowed {
+            Some(allowed) => {
+                // IPv4 peers connected to the IPv6 socket have IPv4-mapped addresses
+                let peer = match peer {
+                    IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(*peer, IpAddr::V4),
+                    _ => *peer,
+                };
+                allowed.iter().any(|net| net.contains(&peer))
+            },
+            None => true,
+        }
+    }
+}
+
+// Parses a peer address or a network in CIDR notation
+// Example: "10.0.0.1" -> 10.0.0.1/32, "10.0.0.0/8" -> 10.0.0.0/8
+pub fn parse_peer(peer: &str) -> Result<IpNet, ConfigError> {
+    if let Ok(net) = peer.parse::<IpNet>() {
+        return Ok(net);
+    }
+    match peer.parse::<IpAddr>() {
+        Ok(ip) => Ok(IpNet::from(ip)),
+        Err(_) => Err(ConfigError::InvalidPeer(peer.into())),
+    }
+}
+
+// Creates a TLS acceptor for the audit log listener. If the client CA is specified, then
+// the clients must present a certificate signed by this CA.
+pub fn tls_acceptor(tls: &BindTls) -> Result<SslAcceptor, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    builder.set_certificate_chain_file(&tls.cert)?;
+    builder.set_private_key_file(&tls.key, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    if let Some(client_ca) = &tls.client_ca {
+        builder.set_ca_file(client_ca)?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(client_ca)?);
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    Ok(builder.build())
+}
+
+// Creates a TLS connector for the standby instances to forward the audit log to the leader's
+// listener. The certificate and key of the listener are the client identity, for the listeners with
+// the client CA. The leader's certificate is verified with the CA certificate, or the system CAs.
+pub fn tls_connector(tls: &BindTls, ca_cert: &Option<String>) -> Result<SslConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    if let Some(ca_cert) = ca_cert {
+        builder.set_ca_file(ca_cert)?;
+    }
+    builder.set_certificate_chain_file(&tls.cert)?;
+    builder.set_private_key_file(&tls.key, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    Ok(builder.build())
+}
+
+// Accepts a TLS connection, the handshake fails if it does not complete within the timeout
+pub fn tls_accept(acceptor: &SslAcceptor, stream: TcpStream, timeout: Duration) -> Result<SslStream<TcpStream>, Box<dyn Error>> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let stream = acceptor.accept(stream).map_err(|error| error.to_string())?;
+    // The audit device keeps the connection open and may be idle for a long time
+    stream.get_ref().set_read_timeout(None)?;
+    stream.get_ref().set_write_timeout(None)?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::{X509, X509NameBuilder};
+
+    use crate::config::BindTls;
+    use crate::listener::{parse_peer, tls_accept, tls_connector, PeerFilter};
+
+    // Self-signed certificate for localhost
+    fn test_identity() -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    #[test]
+    fn test_parse_peer() {
+        assert_eq!(parse_peer("10.0.0.1").unwrap().to_string(), "10.0.0.1/32");
+        assert_eq!(parse_peer("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse_peer("::1").unwrap().to_string(), "::1/128");
+        assert!(parse_peer("10.0.0.0/33").is_err());
+        assert!(parse_peer("vault").is_err());
+    }
+
+    #[test]
+    fn test_peer_filter() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let filter = PeerFilter::new(&None).unwrap();
+        assert!(filter.allows(&ip("192.168.1.1")));
+
+        let filter = PeerFilter::new(&Some(vec!["10.0.0.0/8".into(), "127.0.0.1".into()])).unwrap();
+        assert!(filter.allows(&ip("10.1.2.3")));
+        assert!(filter.allows(&ip("127.0.0.1")));
+        assert!(filter.allows(&ip("::ffff:10.1.2.3")));
+        assert!(!filter.allows(&ip("127.0.0.2")));
+        assert!(!filter.allows(&ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn test_tls_handshake_timeout() {
+        let (cert, key) = test_identity();
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // Connects, but never starts the handshake
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let started = Instant::now();
+        assert!(tls_accept(&acceptor, stream, Duration::from_millis(200)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_tls_connector() {
+        let (cert, key) = test_identity();
+        let dir = std::env::temp_dir().join(format!("vault-sync-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tls = BindTls {
+            cert: dir.join("tls.crt").to_string_lossy().to_string(),
+            key: dir.join("tls.key").to_string_lossy().to_string(),
+            client_ca: None,
+        };
+        fs::write(&tls.cert, cert.to_pem().unwrap()).unwrap();
+        fs::write(&tls.key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = tls_accept(&acceptor, stream, Duration::from_secs(5)).unwrap();
+            let mut line = String::new();
+            stream.read_to_string(&mut line).unwrap();
+            line
+        });
+
+        // The leader's certificate is verified with the CA certificate
+        let connector = tls_connector(&tls, &Some(tls.cert.clone())).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = connector.connect("localhost", stream).unwrap();
+        stream.write_all(b"entry\n").unwrap();
+        stream.shutdown().unwrap();
+        drop(stream);
+        assert_eq!(server.join().unwrap(), "entry\n");
+
+        // The system CAs do not trust the self-signed certificate
+        let connector = tls_connector(&tls, &None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        let (cert, key) = (cert.clone(), key.clone());
+        let server = thread::spawn(move || {
+            let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+            acceptor.set_certificate(&cert).unwrap();
+            acceptor.set_private_key(&key).unwrap();
+            let _ = tls_accept(&acceptor.build(), peer, Duration::from_secs(5));
+        });
+        assert!(connector.connect("localhost", stream).is_err());
+        server.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::{Local, SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value as JsonValue};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unsupported log format: {}", s)),
+        }
+    }
+}
+
+// Logger with the default level and optional per-module levels. The structured fields of the log
+// records (key-value pairs) are printed after the message for the text format, or as separate
+// fields for the json format.
+pub struct Logger {
+    format: LogFormat,
+    level: LevelFilter,
+    // Module path prefix -> level, the longest matching prefix wins
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Logger {
+    // Parses the log level specification, similar to RUST_LOG
+    // Example: "info,vault_sync::sync=debug,hashicorp_vault=warn"
+    pub fn new(format: LogFormat, spec: &str) -> Result<Logger, String> {
+        let mut level = LevelFilter::Info;
+        let mut modules = Vec::new();
+        for part in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match part.split_once('=') {
+                Some((module, module_level)) => {
+                    modules.push((module.to_string(), parse_level(module_level)?));
+                },
+                None => {
+                    level = parse_level(part)?;
+                },
+            }
+        }
+        modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(Logger { format, level, modules })
+    }
+
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let max_level = self.modules.iter().map(|(_, level)| *level).fold(self.level, |a, b| a.max(b));
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        for (module, level) in &self.modules {
+            if target == module || target.starts_with(&format!("{}::", module)) {
+                return *level;
+            }
+        }
+        self.level
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!(
+                    "{} [{}] {}",
+                    Local::now().format("%H:%M:%S"),
+                    record.level(),
+                    record.args(),
+                );
+                for (key, value) in &fields.0 {
+                    match value {
+                        JsonValue::String(value) => write!(line, " {}={}", key, value),
+                        value => write!(line, " {}={}", key, value),
+                    }.unwrap();
+                }
+                line
+            },
+            LogFormat::Json => {
+                let mut map = Map::new();
+                map.insert("time".into(), Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into());
+                map.insert("level".into(), record.level().as_str().into());
+                map.insert("target".into(), record.target().into());
+                map.insert("message".into(), record.args().to_string().into());
+                for (key, value) in fields.0 {
+                    map.insert(key, value);
+                }
+                JsonValue::Object(map).to_string()
+            },
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        // Same as the terminal logger in the mixed mode: errors to stderr, the rest to stdout
+        let _ = if record.level() <= Level::Error {
+            writeln!(io::stderr().lock(), "{}", line)
+        } else {
+            writeln!(io::stdout().lock(), "{}", line)
+        };
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level.trim()).map_err(|_| format!("unsupported log level: {}", level))
+}
+
+struct Fields(Vec<(String, JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_bool() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter, Record};
+    use serde_json::Value;
+
+    use crate::logging::{LogFormat, Logger};
+
+    #[test]
+    fn test_level_spec() {
+        let logger = Logger::new(LogFormat::Text, "warn,vault_sync::sync=debug,vault_sync=info").unwrap();
+        assert_eq!(logger.level("hashicorp_vault::client"), LevelFilter::Warn);
+        assert_eq!(logger.level("vault_sync"), LevelFilter::Info);
+        assert_eq!(logger.level("vault_sync::audit_file"), LevelFilter::Info);
+        assert_eq!(logger.level("vault_sync::sync"), LevelFilter::Debug);
+        assert_eq!(logger.level("vault_sync::syncx"), LevelFilter::Info);
+        assert!(Logger::new(LogFormat::Text, "verbose").is_err());
+    }
+
+    #[test]
+    fn test_json_format() {
+        let logger = Logger::new(LogFormat::Json, "info").unwrap();
+        let fields = [("mount", "secret"), ("op", "update")];
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("vault_sync::sync")
+            .args(format_args!("Creating/updating secret"))
+            .key_values(&fields)
+            .build();
+        let line: Value = serde_json::from_str(&logger.format(&record)).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "Creating/updating secret");
+        assert_eq!(line["mount"], "secret");
+        assert_eq!(line["op"], "update");
+
+        let logger = Logger::new(LogFormat::Text, "info").unwrap();
+        assert!(logger.format(&record).ends_with("[INFO] Creating/updating secret mount=secret op=update"));
+    }
+}
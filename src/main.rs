@@ -1,26 +1,49 @@
-use std::{thread};
+use std::{env, fs, io, thread};
 use std::error::Error;
-use std::net::TcpListener;
+use std::net::{TcpListener, UdpSocket};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 use std::thread::JoinHandle;
+use std::time::Duration;
+use std::process::exit;
 
-use clap::{crate_authors, crate_version, Arg, App};
-use log::{error, info};
-use simplelog::*;
+use chrono::DateTime;
+use clap::{crate_authors, crate_version, Arg, App, SubCommand};
+use log::{error, info, warn};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 
-use config::{VaultHost, VaultSyncConfig};
+use config::{SharedConfig, VaultHost, VaultSyncConfig};
 use vault::VaultClient;
-use crate::config::{EngineVersion, get_backends};
+use crate::config::{BindAddress, EngineVersion, parse_bind};
+use crate::health::HEALTH;
+use crate::leader::Leader;
+use crate::listener::PeerFilter;
+use crate::logging::{LogFormat, Logger};
+use crate::metrics::METRICS;
 
+mod admin;
 mod audit;
+mod audit_device;
+mod audit_file;
+mod check;
 mod config;
+mod control;
+mod health;
+mod leader;
+mod listener;
+mod logging;
+mod metrics;
+mod reload;
+mod replay;
+mod schedule;
+mod shutdown;
 mod sync;
 mod vault;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    TermLogger::init(LevelFilter::Info, Config::default(), TerminalMode::Mixed, ColorChoice::Auto)?;
-
     let matches = App::new("vault-sync")
         .author(crate_authors!())
         .version(crate_version!())
@@ -29,66 +52,244 @@ fn main() -> Result<(), Box<dyn Error>> {
             .value_name("FILE")
             .help("Configuration file")
             .default_value("./vault-sync.yaml")
-            .takes_value(true))
+            .takes_value(true)
+            .global(true))
         .arg(Arg::with_name("dry-run")
             .long("dry-run")
-            .help("Do not do any changes with the destination Vault"))
+            .help("Do not do any changes with the destination Vault")
+            .global(true))
+        .arg(Arg::with_name("log-level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Log level with optional per-module levels, e.g. info,vault_sync::sync=debug (default: RUST_LOG or info)")
+            .takes_value(true)
+            .global(true))
+        .arg(Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("FORMAT")
+            .help("Log format")
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .takes_value(true)
+            .global(true))
         .arg(Arg::with_name("once")
             .long("once")
-            .help("Run the full sync once, then exit"))
+            .help("Run the full sync once, then exit (same as the once command)"))
+        .subcommand(SubCommand::with_name("run")
+            .about("Sync secrets continuously (default)"))
+        .subcommand(SubCommand::with_name("once")
+            .about("Run the full sync once, then exit"))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Show the secrets the full sync would create or update, without any changes to the destination Vault, then exit"))
+        .subcommand(SubCommand::with_name("sync-path")
+            .about("Sync one secret, then exit")
+            .arg(Arg::with_name("mount")
+                .help("Source secrets engine, e.g. secret")
+                .required(true))
+            .arg(Arg::with_name("path")
+                .help("Secret path in the source secrets engine, including the prefix")
+                .required(true))
+            .arg(Arg::with_name("namespace")
+                .long("namespace")
+                .value_name("NAMESPACE")
+                .help("Child namespace of the source namespace, one of src.child_namespaces")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("list")
+            .about("List the source secrets selected by the configuration, then exit"))
+        .subcommand(SubCommand::with_name("status")
+            .about("Show the status of a running vault-sync, using its admin endpoints")
+            .arg(Arg::with_name("address")
+                .long("address")
+                .value_name("HOST:PORT")
+                .help("Admin address of the running vault-sync (default: admin.bind from the configuration)")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("check")
+            .about("Check the source and destination mounts and the token capabilities, then exit"))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Sync the secrets changed according to a captured Vault audit log, then exit")
+            .arg(Arg::with_name("from")
+                .long("from")
+                .value_name("FILE")
+                .help("Vault file audit device log")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .value_name("TIME")
+                .help("Skip the audit log entries before this time (RFC 3339, e.g. 2024-01-01T00:00:00Z)")
+                .takes_value(true)))
         .get_matches();
 
-    let config = load_config(matches.value_of("config").unwrap())?;
-    let (tx, rx): (mpsc::Sender<sync::SecretOp>, mpsc::Receiver<sync::SecretOp>) = mpsc::channel();
+    let log_level = match matches.value_of("log-level") {
+        Some(log_level) => log_level.to_string(),
+        None => env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+    };
+    let log_format = matches.value_of("log-format").unwrap().parse::<LogFormat>()?;
+    Logger::new(log_format, &log_level)?.init()?;
 
-    let log_sync = match &config.bind {
-        Some(_) => Some(log_sync_worker(&config, tx.clone())?),
+    let command = match matches.subcommand_name() {
+        Some(command) => command,
+        None if matches.is_present("once") => "once",
+        None => "run",
+    };
+    let replay = matches.subcommand_matches("replay");
+    let since = match replay.and_then(|replay| replay.value_of("since")) {
+        Some(since) => Some(DateTime::parse_from_rfc3339(since)?),
+        None => None,
+    };
+    let sync_path = matches.subcommand_matches("sync-path");
+    let run_once = command != "run";
+    let dry_run = matches.is_present("dry-run") || command == "diff";
+
+    let config_file = matches.value_of("config").unwrap();
+    let config = load_config(config_file)?;
+
+    // Only the source namespace and the configured child namespaces are synced
+    let sync_path = match sync_path {
+        Some(sync_path) => {
+            Some(sync::requested_secret_path(
+                &config.src,
+                sync_path.value_of("mount").unwrap(),
+                sync_path.value_of("path").unwrap(),
+                sync_path.value_of("namespace"),
+            )?)
+        },
         None => None,
     };
-    info!("Connecting to {}", &config.src.host.url);
+
+    if command == "status" {
+        let address = match matches.subcommand_matches("status").unwrap().value_of("address") {
+            Some(address) => address.to_string(),
+            None => match &config.admin {
+                Some(admin) => admin::local_address(&admin.bind),
+                None => return Err("admin.bind is not set in the configuration, use --address".into()),
+            },
+        };
+        let token = config.admin.as_ref().and_then(|admin| admin.token.as_deref());
+        if !admin::status(&address, token)? {
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let shared_config = SharedConfig::new(config.clone());
+    let (tx, rx): (mpsc::Sender<sync::SecretOp>, mpsc::Receiver<sync::SecretOp>) = mpsc::channel();
+    let (full_sync_trigger, full_sync_requests) = mpsc::channel();
+
+    if !run_once {
+        if config.bind.is_some() {
+            log_sync_worker(&shared_config, tx.clone())?;
+        }
+        if config.audit_file.is_some() {
+            audit_file_worker(&shared_config, tx.clone());
+        }
+        if config.admin.is_some() {
+            admin_worker(&shared_config, tx.clone(), full_sync_trigger.clone())?;
+        }
+    }
+    info!(side = "src", url = config.src.host.url.as_str(); "Connecting to Vault");
     let src_client = vault_client(&config.src.host, &config.src.version, config.src.namespace.clone())?;
     let shared_src_client = Arc::new(Mutex::new(src_client));
-    let src_token = token_worker(&config.src.host, &config.src.version, shared_src_client.clone(), config.src.namespace.clone());
+    HEALTH.set_token("src", true);
 
-    info!("Connecting to {}", &config.dst.host.url);
+    if command == "list" {
+        sync::full_sync(&config.src, shared_src_client.clone(), tx.clone());
+        for op in rx.try_iter() {
+            if let Some(path) = op.secret_path() {
+                match &path.namespace {
+                    Some(namespace) => println!("{}/{}/{}", namespace, &path.mount, &path.path),
+                    None => println!("{}/{}", &path.mount, &path.path),
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    info!(side = "dst", url = config.dst.host.url.as_str(); "Connecting to Vault");
     let dst_client = vault_client(&config.dst.host, &config.dst.version, config.dst.namespace.clone())?;
     let shared_dst_client = Arc::new(Mutex::new(dst_client));
-    let dst_token = token_worker(&config.dst.host, &config.dst.version, shared_dst_client.clone(), config.dst.namespace.clone());
+    HEALTH.set_token("dst", true);
 
-    info!(
-        "Audit device {} exists: {}",
-        &config.id,
-        sync::audit_device_exists(&config.id, shared_src_client.clone()),
-    );
+    if command == "check" {
+        if !check::check(&config, shared_src_client.clone(), shared_dst_client.clone()) {
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let (src_token, src_token_wake) = mpsc::channel();
+    let (dst_token, dst_token_wake) = mpsc::channel();
+    token_worker("src", &shared_config, shared_src_client.clone(), src_token_wake);
+    token_worker("dst", &shared_config, shared_dst_client.clone(), dst_token_wake);
+
+    let manage_audit_device = config.manage_audit_device() && !run_once;
+    if manage_audit_device {
+        if let Err(error) = audit_device::enable_audit_device(&config, shared_src_client.clone()) {
+            error!(audit_device = config.id.as_str(), error:%; "Failed to enable audit device");
+        }
+    } else {
+        info!(
+            audit_device = config.id.as_str(),
+            exists = audit_device::audit_device_exists(&config.id, shared_src_client.clone());
+            "Audit device checked"
+        );
+    }
+
+    // Leader election is only for long running instances
+    let leader = match &config.leader_election {
+        Some(_) if !run_once => Some(Arc::new(Leader::new(&config)?)),
+        _ => None,
+    };
 
     let sync = sync_worker(
         rx,
-        &config,
+        &shared_config,
         shared_src_client.clone(),
         shared_dst_client.clone(),
-        matches.is_present("dry-run"),
-        matches.is_present("once"),
+        dry_run,
+        run_once,
+        leader.clone(),
     );
 
-    let mut join_handlers = vec![sync];
+    // Reload is only for long running instances
+    let reload = if !run_once {
+        let (reload_tx, reload_rx) = mpsc::channel();
+        reload_worker(config_file, &shared_config, reload_rx, shared_src_client.clone(), shared_dst_client.clone(), src_token, dst_token);
+        Some(reload_tx)
+    } else {
+        None
+    };
+
+    let disable_audit_device = manage_audit_device && config.audit_device.as_ref().unwrap().disable_on_shutdown;
+    signal_worker(
+        &shared_config,
+        tx.clone(),
+        reload,
+        if disable_audit_device { Some(shared_src_client.clone()) } else { None },
+    )?;
 
-    if !matches.is_present("once") {
-        let full_sync = full_sync_worker(&config, shared_src_client.clone(), tx.clone());
-        join_handlers.push(full_sync);
-        join_handlers.push(src_token);
-        join_handlers.push(dst_token);
-        if log_sync.is_some() {
-            join_handlers.push(log_sync.unwrap());
+    if !run_once {
+        if let Some(leader) = &leader {
+            leader_election_worker(leader.clone(), shared_dst_client.clone());
         }
+        full_sync_worker(&shared_config, shared_src_client.clone(), tx.clone(), leader.clone(), full_sync_requests);
+    } else if let Some(replay) = replay {
+        replay::replay(&config, replay.value_of("from").unwrap(), since, tx.clone())?;
+    } else if let Some(path) = sync_path {
+        sync::send_op(&tx, sync::SecretOp::Update(path));
+        sync::send_op(&tx, sync::SecretOp::FullSyncFinished);
     } else {
-        let backends = get_backends(&config.src.backend);
-        sync::full_sync(&config.src.prefix, &backends, shared_src_client.clone(), tx.clone());
+        sync::full_sync(&config.src, shared_src_client.clone(), tx.clone());
     };
 
-    // Join all threads
-    for handler in join_handlers {
-        let _ = handler.join();
+    // The sync worker stops after the shutdown or, for the run once mode, after the full sync.
+    // Then the other workers are asked to stop, those blocked on I/O stop when the process exits.
+    let result = sync.join();
+    shutdown::request();
+    if result.is_err() {
+        return Err("sync worker panicked, secrets may not be synced".into());
     }
+    info!("Stopped");
 
     Ok(())
 }
@@ -96,11 +297,11 @@ fn main() -> Result<(), Box<dyn Error>> {
 fn load_config(file_name: &str) -> Result<VaultSyncConfig, Box<dyn Error>> {
     match VaultSyncConfig::from_file(file_name) {
         Ok(config) => {
-            info!("Configuration from {}:\n{}", file_name, serde_json::to_string_pretty(&config).unwrap());
+            info!(file = file_name, config:% = serde_json::to_string_pretty(&config).unwrap(); "Configuration loaded");
             Ok(config)
         },
         Err(error) => {
-            error!("Failed to load configuration file {}: {}", file_name, error);
+            error!(file = file_name, error:%; "Failed to load configuration file");
             Err(error)
         }
     }
@@ -112,61 +313,238 @@ fn vault_client(host: &VaultHost, version: &EngineVersion, namespace: Option<Str
             Ok(client)
         },
         Err(error) => {
-            error!("Failed to connect to {}: {}", &host.url, error);
+            error!(url = host.url.as_str(), error:%; "Failed to connect to Vault");
             Err(error.into())
         }
     }
 }
 
-fn token_worker(host: &VaultHost, version: &EngineVersion, client: Arc<Mutex<VaultClient>>, namespace: Option<String>) -> JoinHandle<()> {
-    let host = host.clone();
-    let version = version.clone();
+fn token_worker(side: &str, config: &SharedConfig, client: Arc<Mutex<VaultClient>>, wake: mpsc::Receiver<()>) -> JoinHandle<()> {
+    let side = side.to_string();
+    let config = config.clone();
     thread::spawn(move || {
-        vault::token_worker(&host, &version, client, namespace.clone());
+        let _guard = HEALTH.worker(&format!("{}-token", &side));
+        vault::token_worker(&side, config, client, wake);
     })
 }
 
 fn sync_worker(
     rx: mpsc::Receiver<sync::SecretOp>,
-    config: &VaultSyncConfig,
+    config: &SharedConfig,
     src_client: Arc<Mutex<VaultClient>>,
     dst_client: Arc<Mutex<VaultClient>>,
     dry_run: bool,
     run_once: bool,
+    leader: Option<Arc<Leader>>,
 ) -> thread::JoinHandle<()> {
-    info!("Dry run: {}", dry_run);
+    info!(dry_run; "Sync worker starting");
     let config = config.clone();
     thread::spawn(move || {
-        sync::sync_worker(rx, &config, src_client, dst_client, dry_run, run_once);
+        let _guard = HEALTH.worker("sync");
+        sync::sync_worker(rx, config, src_client, dst_client, dry_run, run_once, leader);
     })
 }
 
-fn log_sync_worker(config: &VaultSyncConfig, tx: mpsc::Sender<sync::SecretOp>) -> Result<JoinHandle<()>, std::io::Error> {
-    let addr = &config.bind.clone().unwrap();
+fn log_sync_worker(config: &SharedConfig, tx: mpsc::Sender<sync::SecretOp>) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let current_config = config.get();
+    let addr = &current_config.bind.clone().unwrap();
     let config = config.clone();
-    info!("Listening on {}", addr);
-    let listener = TcpListener::bind(addr)?;
-    let handle = thread::spawn(move || {
-        for stream in listener.incoming() {
-            if let Ok(stream) = stream {
-                let tx = tx.clone();
-                let config = config.clone();
-                thread::spawn(move || {
-                    sync::log_sync(&config, stream, tx);
-                });
+    info!(bind = addr.as_str(); "Listening for audit log");
+    let peers = PeerFilter::new(&current_config.bind_allow)?;
+    // The address is already checked by the config validation
+    let handle = match parse_bind(addr).unwrap() {
+        BindAddress::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            // Polls for the connections to stop accepting them on shutdown
+            listener.set_nonblocking(true)?;
+            let acceptor = match &current_config.bind_tls {
+                Some(tls) => Some(Arc::new(listener::tls_acceptor(tls)?)),
+                None => None,
+            };
+            thread::spawn(move || {
+                let _guard = HEALTH.worker("log-sync");
+                while !shutdown::requested() {
+                    let stream = match listener.accept() {
+                        Ok((stream, peer_addr)) => {
+                            if !peers.allows(&peer_addr.ip()) {
+                                warn!(peer:% = peer_addr; "Rejected connection");
+                                continue;
+                            }
+                            info!(peer:% = peer_addr; "New connection");
+                            stream
+                        },
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(shutdown::POLL_INTERVAL);
+                            continue;
+                        },
+                        Err(error) => {
+                            warn!(error:%; "Failed to accept connection");
+                            continue;
+                        }
+                    };
+                    if let Err(error) = stream.set_nonblocking(false) {
+                        warn!(error:%; "Rejected connection");
+                        continue;
+                    }
+                    let tx = tx.clone();
+                    let config = config.clone();
+                    let acceptor = acceptor.clone();
+                    thread::spawn(move || {
+                        match acceptor {
+                            Some(acceptor) => match listener::tls_accept(&acceptor, stream, listener::TLS_HANDSHAKE_TIMEOUT) {
+                                Ok(stream) => sync::log_sync(config, stream, tx),
+                                Err(error) => warn!(error:%; "TLS handshake failed"),
+                            },
+                            None => sync::log_sync(config, stream, tx),
+                        }
+                    });
+                }
+                info!("Stopped listening for audit log");
+            })
+        },
+        BindAddress::Unix(path) => {
+            // Remove the socket left from the previous run
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
+                if metadata.file_type().is_socket() {
+                    fs::remove_file(&path)?;
+                }
             }
-        }
-    });
+            let listener = UnixListener::bind(&path)?;
+            listener.set_nonblocking(true)?;
+            thread::spawn(move || {
+                let _guard = HEALTH.worker("log-sync");
+                while !shutdown::requested() {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(shutdown::POLL_INTERVAL);
+                            continue;
+                        },
+                        Err(error) => {
+                            warn!(error:%; "Failed to accept connection");
+                            continue;
+                        }
+                    };
+                    if let Err(error) = stream.set_nonblocking(false) {
+                        warn!(error:%; "Rejected connection");
+                        continue;
+                    }
+                    info!(path = path.as_str(); "New connection");
+                    let tx = tx.clone();
+                    let config = config.clone();
+                    thread::spawn(move || {
+                        sync::log_sync(config, stream, tx);
+                    });
+                }
+                info!("Stopped listening for audit log");
+            })
+        },
+        BindAddress::Udp(addr) => {
+            let socket = UdpSocket::bind(addr)?;
+            socket.set_read_timeout(Some(shutdown::POLL_INTERVAL))?;
+            thread::spawn(move || {
+                let _guard = HEALTH.worker("log-sync");
+                sync::log_sync_udp(config, socket, &peers, tx);
+            })
+        },
+    };
     Ok(handle)
 }
 
 fn full_sync_worker(
-    config: &VaultSyncConfig,
+    config: &SharedConfig,
     client: Arc<Mutex<VaultClient>>,
-    tx: mpsc::Sender<sync::SecretOp>
+    tx: mpsc::Sender<sync::SecretOp>,
+    leader: Option<Arc<Leader>>,
+    trigger: mpsc::Receiver<()>,
 ) -> thread::JoinHandle<()>{
     let config = config.clone();
     thread::spawn(move || {
-        sync::full_sync_worker(&config, client, tx);
+        let _guard = HEALTH.worker("full-sync");
+        sync::full_sync_worker(config, client, tx, leader, trigger);
+    })
+}
+
+fn leader_election_worker(leader: Arc<Leader>, client: Arc<Mutex<VaultClient>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let _guard = HEALTH.worker("leader-election");
+        leader::leader_election_worker(leader, client);
+    })
+}
+
+fn audit_file_worker(config: &SharedConfig, tx: mpsc::Sender<sync::SecretOp>) -> JoinHandle<()> {
+    let config = config.clone();
+    info!(path = config.get().audit_file.as_ref().unwrap().path.as_str(); "Reading audit log file");
+    thread::spawn(move || {
+        let _guard = HEALTH.worker("audit-file");
+        audit_file::audit_file_sync(config, tx);
     })
 }
+
+fn reload_worker(
+    file_name: &str,
+    config: &SharedConfig,
+    requests: mpsc::Receiver<()>,
+    src_client: Arc<Mutex<VaultClient>>,
+    dst_client: Arc<Mutex<VaultClient>>,
+    src_token: mpsc::Sender<()>,
+    dst_token: mpsc::Sender<()>,
+) -> JoinHandle<()> {
+    let file_name = file_name.to_string();
+    let config = config.clone();
+    thread::spawn(move || {
+        let _guard = HEALTH.worker("reload");
+        reload::reload_worker(&file_name, config, requests, src_client, dst_client, src_token, dst_token);
+    })
+}
+
+// Handles SIGHUP to reload the configuration, and SIGINT or SIGTERM to stop accepting the audit
+// log and scheduling full syncs, then let the sync worker finish the queued secret operations
+fn signal_worker(
+    config: &SharedConfig,
+    tx: mpsc::Sender<sync::SecretOp>,
+    reload: Option<mpsc::Sender<()>>,
+    audit_device_client: Option<Arc<Mutex<VaultClient>>>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let config = config.clone();
+    Ok(thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                match &reload {
+                    Some(reload) => {
+                        let _ = reload.send(());
+                    },
+                    None => info!("Ignoring SIGHUP"),
+                }
+                continue;
+            }
+            let config = config.get();
+            let timeout = Duration::from_secs(config.shutdown_timeout);
+            info!(timeout_s = timeout.as_secs(); "Shutting down, waiting for the queued secrets to sync");
+            shutdown::request();
+            if let Some(client) = &audit_device_client {
+                audit_device::disable_audit_device(&config.id, client.clone());
+            }
+            sync::send_op(&tx, sync::SecretOp::Shutdown);
+            thread::sleep(timeout);
+            warn!(queued = METRICS.queue_depth(); "Shutdown timeout, secrets not synced");
+            exit(1);
+        }
+    }))
+}
+
+fn admin_worker(
+    config: &SharedConfig,
+    tx: mpsc::Sender<sync::SecretOp>,
+    full_sync_trigger: mpsc::Sender<()>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let addr = config.get().admin.as_ref().unwrap().bind.clone();
+    info!(bind = addr.as_str(); "Admin endpoints listening");
+    let server = tiny_http::Server::http(&addr).map_err(|error| error.to_string())?;
+    let config = config.clone();
+    Ok(thread::spawn(move || {
+        let _guard = HEALTH.worker("admin");
+        admin::admin_server(server, config, tx, full_sync_trigger);
+    }))
+}
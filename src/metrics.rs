@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use hashicorp_vault::client::error::Error as VaultError;
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::sync::SecretPath;
+
+// Maximum number of audit events waiting to be synced to measure the latency for, the events
+// forwarded to the leader are never synced by this instance
+const MAX_PENDING_EVENTS: usize = 10000;
+
+// Prometheus metrics, served by the admin endpoint /metrics
+pub struct Metrics {
+    registry: Registry,
+    ops: IntCounterVec,
+    errors: IntCounterVec,
+    audit_events_received: IntCounter,
+    audit_events_filtered: IntCounter,
+    full_sync_duration: Histogram,
+    audit_latency: Histogram,
+    queue_depth: IntGauge,
+    token_ttl: GaugeVec,
+    full_sync_started: Mutex<Option<Instant>>,
+    // Audit log time of the secret operations waiting to be synced
+    pending_events: Mutex<HashMap<SecretPath, DateTime<Utc>>>,
+    // Vault side ("src" or "dst") -> token expiration time
+    token_expires: Mutex<BTreeMap<String, Instant>>,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let ops = IntCounterVec::new(
+            Opts::new("vault_sync_ops_total", "Secret operations by type and result"),
+            &["op", "result"],
+        ).unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("vault_sync_errors_total", "Vault request errors by HTTP status"),
+            &["status"],
+        ).unwrap();
+        let audit_events_received = IntCounter::new(
+            "vault_sync_audit_events_received_total", "Audit log entries received",
+        ).unwrap();
+        let audit_events_filtered = IntCounter::new(
+            "vault_sync_audit_events_filtered_total", "Audit log entries that do not match the source configuration",
+        ).unwrap();
+        let full_sync_duration = Histogram::with_opts(
+            HistogramOpts::new("vault_sync_full_sync_duration_seconds", "Duration of the full sync")
+                .buckets(vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0]),
+        ).unwrap();
+        let audit_latency = Histogram::with_opts(
+            HistogramOpts::new("vault_sync_audit_latency_seconds", "Time from the audit log entry to the destination update")
+                .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        ).unwrap();
+        let queue_depth = IntGauge::new(
+            "vault_sync_queue_depth", "Secret operations waiting for the sync worker",
+        ).unwrap();
+        let token_ttl = GaugeVec::new(
+            Opts::new("vault_sync_token_ttl_seconds", "Remaining TTL of the Vault token"),
+            &["side"],
+        ).unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(ops.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(audit_events_received.clone())).unwrap();
+        registry.register(Box::new(audit_events_filtered.clone())).unwrap();
+        registry.register(Box::new(full_sync_duration.clone())).unwrap();
+        registry.register(Box::new(audit_latency.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(token_ttl.clone())).unwrap();
+
+        Metrics {
+            registry,
+            ops,
+            errors,
+            audit_events_received,
+            audit_events_filtered,
+            full_sync_duration,
+            audit_latency,
+            queue_depth,
+            token_ttl,
+            full_sync_started: Mutex::new(None),
+            pending_events: Mutex::new(HashMap::new()),
+            token_expires: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Result is one of: success, error, unchanged, dry_run
+    pub fn op(&self, op: &str, result: &str) {
+        self.ops.with_label_values(&[op, result]).inc();
+    }
+
+    pub fn vault_error(&self, error: &VaultError) {
+        let status = match error {
+            VaultError::VaultResponse(_, response) => response.status().as_u16().to_string(),
+            VaultError::Timeout(_) => "timeout".to_string(),
+            _ => "none".to_string(),
+        };
+        self.errors.with_label_values(&[&status]).inc();
+    }
+
+    // Counts the audit log entry, the secret path is set if the entry matches the source
+    // configuration
+    pub fn audit_event(&self, time: &str, path: Option<&SecretPath>) {
+        self.audit_events_received.inc();
+        match path {
+            Some(path) => {
+                if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+                    let mut pending_events = self.pending_events.lock().unwrap();
+                    if pending_events.len() >= MAX_PENDING_EVENTS {
+                        pending_events.clear();
+                    }
+                    pending_events.insert(path.clone(), time.with_timezone(&Utc));
+                }
+            },
+            None => self.audit_events_filtered.inc(),
+        }
+    }
+
+    // Observes the audit latency, if the secret was changed according to the audit log
+    pub fn secret_synced(&self, path: &SecretPath) {
+        if let Some(time) = self.pending_events.lock().unwrap().remove(path) {
+            let latency = Utc::now().signed_duration_since(time);
+            self.audit_latency.observe(latency.num_milliseconds().max(0) as f64 / 1000.0);
+        }
+    }
+
+    pub fn full_sync_started(&self) {
+        *self.full_sync_started.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn full_sync_finished(&self) {
+        if let Some(started) = self.full_sync_started.lock().unwrap().take() {
+            self.full_sync_duration.observe(started.elapsed().as_secs_f64());
+        }
+    }
+
+    pub fn queued(&self) {
+        self.queue_depth.inc();
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.dec();
+    }
+
+    pub fn queue_depth(&self) -> i64 {
+        self.queue_depth.get()
+    }
+
+    pub fn set_token_ttl(&self, side: &str, ttl: Option<Duration>) {
+        let mut token_expires = self.token_expires.lock().unwrap();
+        match ttl {
+            Some(ttl) => {
+                token_expires.insert(side.to_string(), Instant::now() + ttl);
+            },
+            None => {
+                token_expires.remove(side);
+            },
+        }
+    }
+
+    // Returns the metrics in the Prometheus text format
+    pub fn gather(&self) -> String {
+        for (side, expires) in self.token_expires.lock().unwrap().iter() {
+            let ttl = expires.saturating_duration_since(Instant::now());
+            self.token_ttl.with_label_values(&[side]).set(ttl.as_secs_f64());
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+    use crate::sync::SecretPath;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let path = SecretPath { mount: "secret".into(), path: "path".into(), namespace: None };
+        metrics.audit_event("2024-01-01T00:00:00Z", Some(&path));
+        metrics.audit_event("2024-01-01T00:00:00Z", None);
+        metrics.op("update", "success");
+        metrics.secret_synced(&path);
+        metrics.queued();
+        metrics.queued();
+        metrics.dequeued();
+        let text = metrics.gather();
+        assert!(text.contains("vault_sync_audit_events_received_total 2"));
+        assert!(text.contains("vault_sync_audit_events_filtered_total 1"));
+        assert!(text.contains(r#"vault_sync_ops_total{op="update",result="success"} 1"#));
+        assert!(text.contains("vault_sync_audit_latency_seconds_count 1"));
+        assert!(text.contains("vault_sync_queue_depth 1"));
+    }
+}
@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use hashicorp_vault::client::SecretsEngine;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::config::{EngineVersion, SharedConfig, VaultHost, VaultSyncConfig};
+use crate::vault::{self, vault_side, VaultClient};
+
+// How often to check the configuration file for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// Reloads the configuration on request (SIGHUP) or when the configuration file changes, for
+// example when Kubernetes updates the mounted ConfigMap
+pub fn reload_worker(
+    file_name: &str,
+    config: SharedConfig,
+    requests: mpsc::Receiver<()>,
+    src_client: Arc<Mutex<VaultClient>>,
+    dst_client: Arc<Mutex<VaultClient>>,
+    src_token: mpsc::Sender<()>,
+    dst_token: mpsc::Sender<()>,
+) {
+    info!("Reload worker started");
+    let mut content = fs::read(file_name).ok();
+    loop {
+        match requests.recv_timeout(POLL_INTERVAL) {
+            Ok(()) => {
+                info!(file = file_name; "Reloading configuration");
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                let new_content = fs::read(file_name).ok();
+                if new_content.is_none() || new_content == content {
+                    continue;
+                }
+                info!(file = file_name; "Configuration file changed, reloading");
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        content = fs::read(file_name).ok();
+        let sides = [("src", src_client.clone(), &src_token), ("dst", dst_client.clone(), &dst_token)];
+        if let Err(error) = reload(file_name, &config, sides) {
+            warn!(file = file_name, error:%; "Failed to reload configuration, keeping the current configuration");
+        }
+    }
+}
+
+// Name of the side, its client and the channel to wake its token worker
+type Side<'a> = (&'a str, Arc<Mutex<VaultClient>>, &'a mpsc::Sender<()>);
+
+fn reload(
+    file_name: &str,
+    config: &SharedConfig,
+    sides: [Side; 2],
+) -> Result<(), Box<dyn Error>> {
+    let mut new_config = VaultSyncConfig::from_file(file_name)?;
+    let old_config = config.get();
+    for field in keep_restart_fields(&old_config, &mut new_config) {
+        warn!(field; "Changing the field requires restart, ignoring");
+    }
+    // The kept fields may not be valid with the new values of the other fields
+    new_config.validate()?;
+
+    // Connect first, so the configuration is not changed if any of the clients fails
+    let mut clients = Vec::new();
+    for (side, client, token_worker) in sides {
+        let (old_host, old_version, _) = vault_side(&old_config, side);
+        let (new_host, new_version, new_namespace) = vault_side(&new_config, side);
+        if connection_changed(old_host, new_host) {
+            info!(side, url = new_host.url.as_str(); "Connecting to Vault");
+            let new_client = vault::vault_client(new_host, new_version, new_namespace.clone())?;
+            clients.push((client, Some(new_client), new_version.clone(), token_worker));
+        } else if old_version != new_version {
+            clients.push((client, None, new_version.clone(), token_worker));
+        }
+    }
+    for (client, new_client, version, token_worker) in clients {
+        let mut client = client.lock().unwrap();
+        match new_client {
+            Some(new_client) => {
+                *client = new_client;
+                // The token worker plans the renewal for the new token
+                let _ = token_worker.send(());
+            },
+            None => client.secrets_engine(match version {
+                EngineVersion::V1 => SecretsEngine::KVV1,
+                EngineVersion::V2 => SecretsEngine::KVV2,
+            }),
+        }
+    }
+
+    config.set(new_config);
+    info!("Configuration reloaded");
+    Ok(())
+}
+
+// The listeners, the audit device and the leader election are set up once at start, keeps the
+// current values for them and returns the names of the changed fields
+fn keep_restart_fields(old: &VaultSyncConfig, new: &mut VaultSyncConfig) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if changed(&old.id, &new.id) {
+        fields.push("id");
+        new.id = old.id.clone();
+    }
+    if changed(&old.bind, &new.bind) {
+        fields.push("bind");
+        new.bind = old.bind.clone();
+    }
+    if changed(&old.bind_tls, &new.bind_tls) {
+        fields.push("bind_tls");
+        new.bind_tls = old.bind_tls.clone();
+    }
+    if changed(&old.bind_allow, &new.bind_allow) {
+        fields.push("bind_allow");
+        new.bind_allow = old.bind_allow.clone();
+    }
+    if changed(&old.audit_device, &new.audit_device) {
+        fields.push("audit_device");
+        new.audit_device = old.audit_device.clone();
+    }
+    if changed(&old.audit_file, &new.audit_file) {
+        fields.push("audit_file");
+        new.audit_file = old.audit_file.clone();
+    }
+    if changed(&old.leader_election, &new.leader_election) {
+        fields.push("leader_election");
+        new.leader_election = old.leader_election.clone();
+    }
+    if changed(&old.admin, &new.admin) {
+        fields.push("admin");
+        new.admin = old.admin.clone();
+    }
+    fields
+}
+
+// True if the client needs to reconnect to Vault
+fn connection_changed(old: &VaultHost, new: &VaultHost) -> bool {
+    old.url != new.url
+        || old.auth != new.auth
+        || old.tls != new.tls
+        || old.timeout != new.timeout
+        || old.connect_timeout != new.connect_timeout
+        || old.proxy != new.proxy
+        || old.no_proxy != new.no_proxy
+        || old.pool_idle_timeout != new.pool_idle_timeout
+        || old.pool_max_idle != new.pool_max_idle
+}
+
+fn changed<T: Serialize>(old: &T, new: &T) -> bool {
+    serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{ConfigError, VaultSyncConfig};
+    use crate::reload::{connection_changed, keep_restart_fields};
+
+    fn config(bind: &str, prefix: &str) -> VaultSyncConfig {
+        let yaml = format!(
+            r#"
+                id: vault-sync
+                full_sync_interval: 60
+                bind: {}
+                src:
+                  url: http://127.0.0.1:8200/
+                  prefix: {}
+                dst:
+                  url: http://127.0.0.1:8200/
+            "#,
+            bind,
+            prefix,
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_keep_restart_fields() {
+        let old = config("0.0.0.0:8202", "src");
+        let mut new = config("0.0.0.0:8203", "src2");
+        assert_eq!(keep_restart_fields(&old, &mut new), vec!["bind"]);
+        assert_eq!(new.bind.as_deref(), Some("0.0.0.0:8202"));
+        assert_eq!(new.src.prefix, "src2");
+    }
+
+    #[test]
+    fn test_keep_restart_fields_validate() {
+        let yaml = |leader_election: &str, version: u8| format!(
+            r#"
+                id: vault-sync
+                full_sync_interval: 60
+                bind: 0.0.0.0:8202
+                {}
+                src:
+                  url: http://127.0.0.1:8200/
+                  backend: secret
+                dst:
+                  url: http://127.0.0.1:8200/
+                  backend: secret
+                  version: {}
+            "#,
+            leader_election,
+            version,
+        );
+        let old: VaultSyncConfig = serde_yaml::from_str(&yaml("leader_election: {path: vault-sync/leader, advertise: vault-sync-0:8202}", 2)).unwrap();
+        let mut new: VaultSyncConfig = serde_yaml::from_str(&yaml("", 1)).unwrap();
+        assert!(new.validate().is_ok());
+        // The lock requires KV v2 in the destination
+        assert_eq!(keep_restart_fields(&old, &mut new), vec!["leader_election"]);
+        assert_eq!(new.validate().unwrap_err().to_string(), ConfigError::LeaderElectionRequiresBackend.to_string());
+    }
+
+    #[test]
+    fn test_connection_changed() {
+        let old = config("0.0.0.0:8202", "src");
+        let mut new = config("0.0.0.0:8202", "src2");
+        assert!(!connection_changed(&old.src.host, &new.src.host));
+        new.src.host.timeout = Some(10);
+        assert!(connection_changed(&old.src.host, &new.src.host));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc;
+
+use chrono::{DateTime, FixedOffset};
+use log::{info, warn};
+
+use crate::audit;
+use crate::config::VaultSyncConfig;
+use crate::sync::{audit_log_op, send_op, AuditFilter, SecretOp};
+
+// Sends the secret operations from a captured audit log to the sync thread, then signals the end
+// of the sync
+pub fn replay(
+    config: &VaultSyncConfig,
+    file_name: &str,
+    since: Option<DateTime<FixedOffset>>,
+    tx: mpsc::Sender<SecretOp>,
+) -> Result<(), Box<dyn Error>> {
+    info!(file = file_name; "Replaying audit log");
+    let filter = AuditFilter::new(&config.src);
+    let file = File::open(file_name)?;
+    let ops = replay_ops(&filter, BufReader::new(file), since)?;
+    info!(secrets = ops.len(); "Secrets to sync");
+    for op in ops {
+        send_op(&tx, op);
+    }
+    send_op(&tx, SecretOp::FullSyncFinished);
+    Ok(())
+}
+
+// Converts the audit log entries to the secret operations, only the last operation for each secret
+// is kept, in the order of the last operations
+fn replay_ops<R: BufRead>(
+    filter: &AuditFilter,
+    reader: R,
+    since: Option<DateTime<FixedOffset>>,
+) -> Result<Vec<SecretOp>, Box<dyn Error>> {
+    let mut ops: Vec<Option<SecretOp>> = Vec::new();
+    let mut index = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let audit_log: audit::AuditLog = match serde_json::from_str(&line) {
+            Ok(audit_log) => audit_log,
+            Err(error) => {
+                warn!(error:%, entry = line.as_str(); "Failed to deserialize audit log entry");
+                continue;
+            }
+        };
+        if let Some(since) = since {
+            match DateTime::parse_from_rfc3339(&audit_log.time) {
+                Ok(time) if time < since => continue,
+                Ok(_) => {},
+                Err(error) => {
+                    warn!(time = audit_log.time.as_str(), error:%; "Failed to parse time");
+                    continue;
+                }
+            }
+        }
+        if let Some(op) = audit_log_op(filter, &audit_log) {
+            let path = op.secret_path().unwrap().clone();
+            if let Some(previous) = index.insert(path, ops.len()) {
+                ops[previous] = None;
+            }
+            ops.push(Some(op));
+        }
+    }
+    Ok(ops.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use crate::config::VaultSource;
+    use crate::replay::replay_ops;
+    use crate::sync::{AuditFilter, SecretOp};
+
+    fn entry(time: &str, operation: &str, path: &str) -> String {
+        format!(
+            r#"{{"time": "{}", "type": "response", "request": {{"operation": "{}", "mount_type": "kv", "path": "{}", "namespace": {{"id": "root"}}}}}}"#,
+            time,
+            operation,
+            path,
+        )
+    }
+
+    fn filter() -> AuditFilter {
+        let src: VaultSource = serde_yaml::from_str("url: http://127.0.0.1:8200/\nbackend: secret").unwrap();
+        AuditFilter::new(&src)
+    }
+
+    #[test]
+    fn test_replay_ops() {
+        let log = [
+            entry("2024-01-01T10:00:00.000000Z", "create", "secret/data/foo"),
+            entry("2024-01-01T11:00:00.000000Z", "update", "secret/data/bar"),
+            entry("2024-01-01T12:00:00.000000Z", "read", "secret/data/baz"),
+            "not json".to_string(),
+            entry("2024-01-01T13:00:00.000000Z", "delete", "secret/data/foo"),
+            entry("2024-01-01T14:00:00.000000Z", "update", "other/data/foo"),
+        ].join("\n");
+
+        let ops = replay_ops(&filter(), log.as_bytes(), None).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert!(matches!(&ops[0], SecretOp::Update(path) if path.path == "bar"));
+        assert!(matches!(&ops[1], SecretOp::Delete(path) if path.path == "foo"));
+
+        let since = DateTime::parse_from_rfc3339("2024-01-01T11:30:00Z").unwrap();
+        let ops = replay_ops(&filter(), log.as_bytes(), Some(since)).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(&ops[0], SecretOp::Delete(path) if path.path == "foo"));
+    }
+}
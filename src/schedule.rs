@@ -0,0 +1,165 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use cron::Schedule;
+use rand::Rng;
+
+use crate::config::{ConfigError, MaintenanceWindows, VaultSyncConfig};
+
+// Allowed hours in UTC, "HH:MM-HH:MM". The end may be before the start, for a window that crosses
+// midnight.
+#[derive(Debug, PartialEq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl FromStr for TimeWindow {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<TimeWindow, ConfigError> {
+        let invalid = || ConfigError::InvalidMaintenanceWindow(s.into());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(TimeWindow { start: parse(start)?, end: parse(end)? })
+    }
+}
+
+impl TimeWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    // Returns the next time the window opens, after `now`
+    fn next_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let start = now.date_naive().and_time(self.start).and_utc();
+        if start > now { start } else { start + Duration::days(1) }
+    }
+}
+
+pub fn parse_schedule(schedule: &str) -> Result<Schedule, ConfigError> {
+    Schedule::from_str(schedule).map_err(|error| ConfigError::InvalidFullSyncSchedule(schedule.into(), error.to_string()))
+}
+
+fn time_windows(windows: &MaintenanceWindows) -> Vec<TimeWindow> {
+    windows.hours.iter().filter_map(|window| window.parse().ok()).collect()
+}
+
+// Returns true if `now` is in one of the maintenance windows, or there are no windows
+pub fn in_window(windows: &MaintenanceWindows, now: DateTime<Utc>) -> bool {
+    let windows = time_windows(windows);
+    windows.is_empty() || windows.iter().any(|window| window.contains(now.time()))
+}
+
+// Returns `now` if it is in one of the maintenance windows, otherwise the time the next window opens
+pub fn window_open(windows: &MaintenanceWindows, now: DateTime<Utc>) -> DateTime<Utc> {
+    if in_window(windows, now) {
+        return now;
+    }
+    time_windows(windows).iter().map(|window| window.next_start(now)).min().unwrap_or(now)
+}
+
+// True if the destination secrets can be deleted now
+pub fn delete_allowed(config: &VaultSyncConfig, now: DateTime<Utc>) -> bool {
+    match &config.maintenance_windows {
+        Some(windows) if windows.delete => in_window(windows, now),
+        _ => true,
+    }
+}
+
+// Returns the time of the next full sync. With `full_sync_schedule`, the next time matching the
+// cron expression, otherwise `full_sync_interval` after the start of the last full sync, so the
+// duration of the full sync does not shift the schedule. Then adds a random splay and moves the time
+// into the maintenance window.
+pub fn next_full_sync(config: &VaultSyncConfig, started: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+    let interval = Duration::seconds(config.full_sync_interval as i64);
+    let next = match &config.full_sync_schedule {
+        Some(schedule) => parse_schedule(schedule).ok()
+            .and_then(|schedule| schedule.after(&now).next())
+            .unwrap_or(started + interval),
+        None => started + interval,
+    };
+    let next = next + splay(config.full_sync_splay);
+    match &config.maintenance_windows {
+        Some(windows) if windows.full_sync => window_open(windows, next),
+        _ => next,
+    }
+}
+
+fn splay(max_seconds: u64) -> Duration {
+    if max_seconds == 0 {
+        return Duration::zero();
+    }
+    Duration::seconds(rand::thread_rng().gen_range(0..=max_seconds) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveTime, Utc};
+
+    use crate::config::{MaintenanceWindows, VaultSyncConfig};
+    use crate::schedule::{delete_allowed, in_window, next_full_sync, window_open, TimeWindow};
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn config(extra: &str) -> VaultSyncConfig {
+        let yaml = format!(
+            r#"
+                id: vault-sync
+                full_sync_interval: 3600
+                src:
+                  url: http://127.0.0.1:8200/
+                dst:
+                  url: http://127.0.0.1:8200/
+                {}
+            "#,
+            extra,
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_time_window() {
+        let window: TimeWindow = "22:00-06:00".parse().unwrap();
+        assert!(window.contains(NaiveTime::from_hms_opt(23, 0, 0).unwrap()));
+        assert!(window.contains(NaiveTime::from_hms_opt(5, 59, 0).unwrap()));
+        assert!(!window.contains(NaiveTime::from_hms_opt(6, 0, 0).unwrap()));
+        assert!("22:00".parse::<TimeWindow>().is_err());
+        assert!("25:00-06:00".parse::<TimeWindow>().is_err());
+
+        let windows = MaintenanceWindows { hours: vec!["01:00-03:00".into()], full_sync: true, delete: false };
+        assert!(in_window(&windows, time("2024-01-01T02:00:00Z")));
+        assert_eq!(window_open(&windows, time("2024-01-01T04:00:00Z")), time("2024-01-02T01:00:00Z"));
+        assert_eq!(window_open(&windows, time("2024-01-01T00:30:00Z")), time("2024-01-01T01:00:00Z"));
+    }
+
+    #[test]
+    fn test_next_full_sync() {
+        let started = time("2024-01-01T10:00:00Z");
+        let now = time("2024-01-01T10:20:00Z");
+        assert_eq!(next_full_sync(&config(""), started, now), time("2024-01-01T11:00:00Z"));
+        let config = config(r#"full_sync_schedule: "0 30 * * * *""#);
+        assert_eq!(next_full_sync(&config, started, now), time("2024-01-01T10:30:00Z"));
+    }
+
+    #[test]
+    fn test_maintenance_windows() {
+        let config = config(r#"
+                maintenance_windows:
+                  hours: ["01:00-03:00"]
+                  delete: true
+        "#);
+        let started = time("2024-01-01T10:00:00Z");
+        assert_eq!(next_full_sync(&config, started, started), time("2024-01-02T01:00:00Z"));
+        assert!(!delete_allowed(&config, started));
+        assert!(delete_allowed(&config, time("2024-01-01T01:30:00Z")));
+    }
+}
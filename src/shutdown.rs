@@ -0,0 +1,17 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Interval for the listeners to check for the shutdown while there are no connections
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Set on SIGINT or SIGTERM: the workers stop accepting the audit log and scheduling full syncs,
+// while the sync worker finishes the queued secret operations
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn request() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
@@ -1,53 +1,52 @@
 use std::{thread, time};
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::net::UdpSocket;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
 
-use hashicorp_vault::client::{EndpointResponse, HttpVerb};
+use chrono::{SecondsFormat, Utc};
 use log::{debug, info, warn};
 use serde_json::Value;
 
 use crate::audit;
-use crate::config::{EngineVersion, get_backends, VaultSyncConfig};
-use crate::vault::VaultClient;
-
-pub fn audit_device_exists(name: &str, client: Arc<Mutex<VaultClient>>) -> bool {
-    let client = client.lock().unwrap();
-    let name = format!("{}/", name);
-    match client.call_endpoint::<Value>(HttpVerb::GET, "sys/audit", None, None) {
-        Ok(response) => {
-            debug!("GET sys/audit: {:?}", response);
-            if let EndpointResponse::VaultResponse(response) = response {
-                if let Some(Value::Object(map)) = response.data {
-                    for (key, _) in &map {
-                        if key == &name {
-                            return true;
-                        }
-                    }
-                }
-            }
-        },
-        Err(error) => {
-            warn!("GET sys/audit: {}", error);
-        }
-    }
-    false
-}
+use crate::config::{EngineVersion, get_backends, SharedConfig, VaultSource, VaultSyncConfig};
+use crate::control::CONTROL;
+use crate::health::HEALTH;
+use crate::leader::Leader;
+use crate::listener::PeerFilter;
+use crate::metrics::METRICS;
+use crate::schedule;
+use crate::shutdown;
+use crate::vault::{retry_on_timeout, VaultClient};
+
+// How often to check the maintenance window if there are deferred deletes
+const DEFERRED_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
 
 pub fn full_sync_worker(
-    config: &VaultSyncConfig,
+    config: SharedConfig,
     client: Arc<Mutex<VaultClient>>,
-    tx: mpsc::Sender<SecretOp>
+    tx: mpsc::Sender<SecretOp>,
+    leader: Option<Arc<Leader>>,
+    trigger: mpsc::Receiver<()>,
 ) {
     info!("FullSync worker started");
-    let interval = time::Duration::from_secs(config.full_sync_interval);
-    let prefix = &config.src.prefix;
-    let backends = get_backends(&config.src.backend);
     loop {
-        full_sync(prefix, &backends, client.clone(), tx.clone());
-        thread::sleep(interval);
+        if let Some(leader) = &leader {
+            leader.wait();
+        }
+        if shutdown::requested() {
+            break;
+        }
+        let started = Utc::now();
+        full_sync(&config.get().src, client.clone(), tx.clone());
+        // Wait for the next full sync, or for the admin API to trigger it
+        let next = schedule::next_full_sync(&config.get(), started, Utc::now());
+        info!(next:% = next.to_rfc3339_opts(SecondsFormat::Secs, true); "Next full sync scheduled");
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        if let Err(mpsc::RecvTimeoutError::Disconnected) = trigger.recv_timeout(wait) {
+            thread::sleep(wait);
+        }
     }
 }
 
@@ -57,17 +56,30 @@ struct Item {
     index: usize,
 }
 
-pub fn full_sync(prefix: &str, backends: &Vec<String>, client: Arc<Mutex<VaultClient>>, tx: mpsc::Sender<SecretOp>) {
-    let prefix= normalize_prefix(prefix);
+pub fn full_sync(src: &VaultSource, client: Arc<Mutex<VaultClient>>, tx: mpsc::Sender<SecretOp>) {
+    let prefix= normalize_prefix(&src.prefix);
+    let backends = get_backends(&src.backend);
     info!("FullSync started");
+    METRICS.full_sync_started();
     let now = time::Instant::now();
-    for backend in backends {
-        full_sync_internal(&prefix, backend, client.clone(), tx.clone());
+    for child_namespace in source_namespaces(src) {
+        let namespace = src_namespace(src, &child_namespace);
+        for backend in &backends {
+            full_sync_internal(&prefix, backend, &namespace, &child_namespace, client.clone(), tx.clone());
+        }
     }
-    info!("FullSync finished in {}ms", now.elapsed().as_millis());
+    send_op(&tx, SecretOp::FullSyncFinished);
+    info!(duration_ms = now.elapsed().as_millis() as u64; "FullSync finished");
 }
 
-fn full_sync_internal(prefix: &str, backend: &str, client: Arc<Mutex<VaultClient>>, tx: mpsc::Sender<SecretOp>) {
+fn full_sync_internal(
+    prefix: &str,
+    backend: &str,
+    namespace: &Option<String>,
+    child_namespace: &Option<String>,
+    client: Arc<Mutex<VaultClient>>,
+    tx: mpsc::Sender<SecretOp>,
+) {
     let mut stack: Vec<Item> = Vec::new();
     let item = Item {
         parent: prefix.to_string(),
@@ -76,21 +88,27 @@ fn full_sync_internal(prefix: &str, backend: &str, client: Arc<Mutex<VaultClient
     };
     stack.push(item);
 
-    'outer: while stack.len() > 0 {
+    'outer: while !stack.is_empty() {
+        if shutdown::requested() {
+            info!("FullSync interrupted by shutdown");
+            break;
+        }
         let len = stack.len();
         let item = stack.get_mut(len - 1).unwrap();
         if item.secrets.is_none() {
-            let secrets = {
+            let secrets = retry_on_timeout(|| {
                 let mut client = client.lock().unwrap();
+                client.namespace = namespace.clone();
                 client.secret_backend(backend);
                 client.list_secrets(&item.parent)
-            };
+            });
             match secrets {
                 Ok(secrets) => {
                     item.secrets = Some(secrets);
                 },
                 Err(error) => {
-                    warn!("Failed to list secrets in {}: {}", &item.parent, error);
+                    warn!(mount = backend, src_path = item.parent.as_str(), error:%; "Failed to list secrets");
+                    METRICS.vault_error(&error);
                 }
             }
         }
@@ -108,31 +126,21 @@ fn full_sync_internal(prefix: &str, backend: &str, client: Arc<Mutex<VaultClient
                     continue 'outer;
                 } else {
                     let full_name = format!("{}{}", &item.parent, &secret);
-                    let op = SecretOp::Create(SecretPath { mount: backend.to_string(), path: full_name });
-                    if let Err(error) = tx.send(op) {
-                        warn!("Failed to send a secret to a sync thread: {}", error);
-                    }
+                    let op = SecretOp::Create(SecretPath {
+                        mount: backend.to_string(),
+                        path: full_name,
+                        namespace: child_namespace.clone(),
+                    });
+                    send_op(&tx, op);
                 }
             }
         }
         stack.pop();
     }
-    let _ = tx.send(SecretOp::FullSyncFinished);
 }
 
-pub fn log_sync(config: &VaultSyncConfig, stream: TcpStream, tx: mpsc::Sender<SecretOp>) {
-    match stream.peer_addr() {
-        Ok(peer_addr) => {
-            info!("New connection from {}", peer_addr);
-        },
-        Err(_) => {
-            info!("New connection");
-        }
-    }
-    let backends = get_backends(&config.src.backend);
-    let prefix = &config.src.prefix;
-    let version = &config.src.version;
-
+// Reads the audit log entries, one per line, from a TCP or Unix socket connection
+pub fn log_sync<S: Read>(config: SharedConfig, stream: S, tx: mpsc::Sender<SecretOp>) {
     let mut reader = BufReader::new(stream);
     loop {
         let mut line = String::new();
@@ -142,23 +150,14 @@ pub fn log_sync(config: &VaultSyncConfig, stream: TcpStream, tx: mpsc::Sender<Se
                 break;
             },
             Ok(_) => {
-                debug!("Log: '{}'", line.trim());
-                let audit_log: Result<audit::AuditLog, _> = serde_json::from_str(&line);
-                match audit_log {
-                    Ok(audit_log) => {
-                        if let Some(op) = audit_log_op(&backends, &prefix, &version, &audit_log) {
-                            if let Err(error) = tx.send(op) {
-                                warn!("Failed to send a secret to a sync thread: {}", error);
-                            }
-                        }
-                    },
-                    Err(error) => {
-                        warn!("Failed to deserialize: {}, response: {}", error, &line);
-                    }
+                if shutdown::requested() {
+                    break;
                 }
+                let filter = AuditFilter::new(&config.get().src);
+                audit_log_line(&filter, &line, &tx);
             },
             Err(error) => {
-                warn!("Error: {}", error);
+                warn!(error:%; "Failed to read audit log connection");
                 break;
             }
         }
@@ -166,10 +165,66 @@ pub fn log_sync(config: &VaultSyncConfig, stream: TcpStream, tx: mpsc::Sender<Se
     debug!("Closed connection");
 }
 
-#[derive(Debug)]
+// Reads the audit log entries from UDP datagrams, each datagram is one entry. The socket read
+// timeout is the interval to check for the shutdown.
+pub fn log_sync_udp(config: SharedConfig, socket: UdpSocket, peers: &PeerFilter, tx: mpsc::Sender<SecretOp>) {
+    let mut buf = vec![0u8; 65536];
+    while !shutdown::requested() {
+        match socket.recv_from(&mut buf) {
+            Ok((n, peer_addr)) => {
+                if shutdown::requested() {
+                    break;
+                }
+                if !peers.allows(&peer_addr.ip()) {
+                    warn!(peer:% = peer_addr; "Rejected audit log entry");
+                    continue;
+                }
+                let entry = String::from_utf8_lossy(&buf[..n]);
+                let filter = AuditFilter::new(&config.get().src);
+                audit_log_line(&filter, &entry, &tx);
+            },
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+            Err(error) => {
+                warn!(error:%; "Failed to receive audit log entry");
+            }
+        }
+    }
+    info!("Stopped listening for audit log");
+}
+
+// Parse one audit log entry and send the corresponding SecretOp, if any, to the sync thread
+pub fn audit_log_line(filter: &AuditFilter, line: &str, tx: &mpsc::Sender<SecretOp>) {
+    debug!(entry = line.trim(); "Audit log entry");
+    let audit_log: Result<audit::AuditLog, _> = serde_json::from_str(line);
+    match audit_log {
+        Ok(audit_log) => {
+            let op = audit_log_op(filter, &audit_log);
+            METRICS.audit_event(&audit_log.time, op.as_ref().and_then(|op| op.secret_path()));
+            if let Some(op) = op {
+                send_op(tx, op);
+            }
+        },
+        Err(error) => {
+            warn!(error:%, entry = line.trim(); "Failed to deserialize audit log entry");
+        }
+    }
+}
+
+// Sends the secret operation to the sync thread
+pub fn send_op(tx: &mpsc::Sender<SecretOp>, op: SecretOp) {
+    METRICS.queued();
+    if let Err(error) = tx.send(op) {
+        METRICS.dequeued();
+        warn!(error:%; "Failed to send a secret to a sync thread");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SecretPath{
-    mount: String,
-    path: String,
+    pub mount: String,
+    pub path: String,
+    // Child namespace relative to the source namespace, None for the source namespace itself
+    pub namespace: Option<String>,
 }
 
 #[derive(Debug)]
@@ -178,6 +233,17 @@ pub enum SecretOp {
     Update(SecretPath),
     Delete(SecretPath),
     FullSyncFinished,
+    // Stops the sync worker, the operations queued before it are synced
+    Shutdown,
+}
+
+impl SecretOp {
+    pub fn secret_path(&self) -> Option<&SecretPath> {
+        match self {
+            SecretOp::Create(path) | SecretOp::Update(path) | SecretOp::Delete(path) => Some(path),
+            SecretOp::FullSyncFinished | SecretOp::Shutdown => None,
+        }
+    }
 }
 
 struct SyncStats {
@@ -193,121 +259,274 @@ impl SyncStats {
         self.updated = 0;
         self.deleted = 0;
     }
+    // Counts the result of the secret operation. Only the secrets actually written or deleted are
+    // counted, the dry run results are reported only in the metrics.
+    fn record(&mut self, op: &str, result: &str) {
+        METRICS.op(op, result);
+        match (op, result) {
+            ("create" | "update", "success") => self.updated += 1,
+            ("delete", "success") => self.deleted += 1,
+            _ => {},
+        }
+    }
 }
 
 pub fn sync_worker(
     rx: mpsc::Receiver<SecretOp>,
-    config: &VaultSyncConfig,
+    config: SharedConfig,
     src_client: Arc<Mutex<VaultClient>>,
     dst_client: Arc<Mutex<VaultClient>>,
     dry_run: bool,
     run_once: bool,
+    leader: Option<Arc<Leader>>,
 ) {
-    let src_prefix = normalize_prefix(&config.src.prefix);
-    let dst_prefix = normalize_prefix(&config.dst.prefix);
-    let src_mounts = get_backends(&config.src.backend);
-    let dst_mounts = get_backends(&config.dst.backend);
-    let mount_map: HashMap<&str, &str> = src_mounts.iter().map(|s| s.as_str()).zip(dst_mounts.iter().map(|s| s.as_str())).collect();
     info!("Sync worker started");
     let mut stats = SyncStats::new();
+    // Sequence number of the secret operation, to correlate the log records
+    let mut job: u64 = 0;
+    // Deletes waiting for the maintenance window
+    let mut deferred: Vec<SecretOp> = Vec::new();
     loop {
-        let op = rx.recv();
+        let op = if !deferred.is_empty() && schedule::delete_allowed(&config.get(), Utc::now()) {
+            Ok(deferred.remove(0))
+        } else if !deferred.is_empty() {
+            match rx.recv_timeout(DEFERRED_CHECK_INTERVAL) {
+                Ok(op) => {
+                    METRICS.dequeued();
+                    Ok(op)
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => Err(mpsc::RecvError),
+            }
+        } else {
+            rx.recv().inspect(|_| METRICS.dequeued())
+        };
         if let Ok(op) = op {
+            while CONTROL.paused() && !shutdown::requested() {
+                thread::sleep(time::Duration::from_secs(1));
+            }
+            job += 1;
+            if let Some(leader) = &leader {
+                if !leader.is_leader() && !matches!(op, SecretOp::Shutdown) {
+                    leader.forward(&op);
+                    continue;
+                }
+            }
+            // The configuration can be reloaded between the operations
+            let current_config = config.get();
+            let config = current_config.as_ref();
+            let src_mounts = get_backends(&config.src.backend);
+            let dst_mounts = get_backends(&config.dst.backend);
+            let mount_map: HashMap<&str, &str> = src_mounts.iter().map(|s| s.as_str()).zip(dst_mounts.iter().map(|s| s.as_str())).collect();
+            if let Some(path) = op.secret_path() {
+                if !mount_map.contains_key(path.mount.as_str()) {
+                    warn!(mount = path.mount.as_str(), src_path = path.path.as_str(); "Secrets engine is not configured, skipping");
+                    continue;
+                }
+                // The child namespace can be removed from the configuration while the operation is queued
+                if !is_child_namespace(&config.src, &path.namespace) {
+                    warn!(namespace = path.namespace.as_deref(), src_path = path.path.as_str(); "Child namespace is not configured, skipping");
+                    continue;
+                }
+            }
+            let op_name = if matches!(op, SecretOp::Create(_)) { "create" } else { "update" };
             match op {
                 SecretOp::Update(path) | SecretOp::Create(path) => {
+                    // The secret was created again after the deferred delete
+                    deferred.retain(|deferred| deferred.secret_path() != Some(&path));
                     let src_path = &path.path;
-                    let dst_path = secret_src_to_dst_path(&src_prefix, &dst_prefix, &src_path);
-                    let src_secret: Result<Value, _> = {
+                    let dst_path = dst_secret_path(config, src_path);
+                    let src_namespace = src_namespace(&config.src, &path.namespace);
+                    let dst_namespace = dst_namespace(config, &path.namespace);
+                    let src_secret: Result<Value, _> = retry_on_timeout(|| {
                         let mut client = src_client.lock().unwrap();
+                        client.namespace = src_namespace.clone();
                         client.secret_backend(&path.mount);
-                        client.get_custom_secret(&src_path)
-                    };
-                    let dst_secret: Result<Value, _> = {
+                        client.get_custom_secret(src_path)
+                    });
+                    let dst_secret: Result<Value, _> = retry_on_timeout(|| {
                         let mut client = dst_client.lock().unwrap();
+                        client.namespace = dst_namespace.clone();
                         client.secret_backend(mount_map[path.mount.as_str()]);
                         client.get_custom_secret(&dst_path)
-                    };
+                    });
+                    let mount = path.mount.as_str();
                     if let Err(error) = src_secret {
-                        warn!("Failed to get secret {}: {}", &src_path, error);
+                        warn!(job, op = op_name, mount, src_path = src_path.as_str(), error:%; "Failed to get secret");
+                        METRICS.vault_error(&error);
+                        stats.record(op_name, "error");
                         continue;
                     }
                     let src_secret = src_secret.unwrap();
-                    if let Ok(dst_secret) = dst_secret {
-                        if dst_secret == src_secret {
+                    if let Ok(dst_secret) = &dst_secret {
+                        if *dst_secret == src_secret {
+                            stats.record(op_name, "unchanged");
+                            METRICS.secret_synced(&path);
                             continue;
                         }
                     }
-                    info!("Creating/updating secret {}", &dst_path);
+                    let patch = match &dst_secret {
+                        Ok(dst_secret) if config.dst.patch => secret_merge_patch(dst_secret, &src_secret),
+                        _ => None,
+                    };
+                    info!(job, op = op_name, mount, src_path = src_path.as_str(), dst_path = dst_path.as_str(); "Creating/updating secret");
                     if !dry_run {
-                        let result = {
-                            let client = dst_client.lock().unwrap();
-                            client.set_custom_secret(&dst_path, &src_secret)
-                        };
+                        let result = retry_on_timeout(|| {
+                            let mut client = dst_client.lock().unwrap();
+                            client.namespace = dst_namespace.clone();
+                            client.secret_backend(mount_map[path.mount.as_str()]);
+                            match &patch {
+                                Some(patch) => client.patch_custom_secret(&dst_path, patch),
+                                None => client.set_custom_secret(&dst_path, &src_secret),
+                            }
+                        });
                         if let Err(error) = result {
-                            warn!("Failed to set secret {}: {}", &dst_path, error);
+                            warn!(job, op = op_name, mount, dst_path = dst_path.as_str(), error:%; "Failed to set secret");
+                            METRICS.vault_error(&error);
+                            stats.record(op_name, "error");
                         } else {
-                            stats.updated += 1;
+                            stats.record(op_name, "success");
+                            METRICS.secret_synced(&path);
                         }
+                    } else {
+                        stats.record(op_name, "dry_run");
                     }
                 },
                 SecretOp::Delete(path) => {
-                    let secret = secret_src_to_dst_path(&src_prefix, &dst_prefix, &path.path);
-                    info!("Deleting secret {}", &secret);
+                    let dst_path = dst_secret_path(config, &path.path);
+                    let mount = path.mount.as_str();
+                    if !schedule::delete_allowed(config, Utc::now()) {
+                        info!(job, op = "delete", mount, src_path = path.path.as_str(), dst_path = dst_path.as_str(); "Deferring delete until the maintenance window");
+                        deferred.push(SecretOp::Delete(path));
+                        continue;
+                    }
+                    info!(job, op = "delete", mount, src_path = path.path.as_str(), dst_path = dst_path.as_str(); "Deleting secret");
                     if !dry_run {
-                        let mut client = dst_client.lock().unwrap();
-                        client.secret_backend(mount_map[path.mount.as_str()]);
-                        let _ = client.delete_secret(&path.path);
+                        let result = retry_on_timeout(|| {
+                            let mut client = dst_client.lock().unwrap();
+                            client.namespace = dst_namespace(config, &path.namespace);
+                            client.secret_backend(mount_map[path.mount.as_str()]);
+                            client.delete_secret(&dst_path)
+                        });
+                        if let Err(error) = result {
+                            warn!(job, op = "delete", mount, dst_path = dst_path.as_str(), error:%; "Failed to delete secret");
+                            METRICS.vault_error(&error);
+                            stats.record("delete", "error");
+                        } else {
+                            stats.record("delete", "success");
+                            METRICS.secret_synced(&path);
+                        }
                     } else {
-                        stats.deleted += 1;
+                        stats.record("delete", "dry_run");
                     }
                 },
                 SecretOp::FullSyncFinished => {
-                    info!("Secrets created/updated: {}, deleted: {}", &stats.updated, &stats.deleted);
+                    HEALTH.set_full_sync_finished();
+                    CONTROL.set_last_full_sync(stats.updated, stats.deleted);
+                    METRICS.full_sync_finished();
+                    info!(updated = stats.updated, deleted = stats.deleted; "Secrets created/updated and deleted");
                     stats.reset();
                     if run_once {
+                        if !deferred.is_empty() {
+                            warn!(deferred = deferred.len(); "Secrets not deleted outside the maintenance window");
+                        }
                         break;
                     }
                 },
+                SecretOp::Shutdown => {
+                    info!(updated = stats.updated, deleted = stats.deleted; "Secrets created/updated and deleted");
+                    if !deferred.is_empty() {
+                        warn!(deferred = deferred.len(); "Secrets not deleted outside the maintenance window");
+                    }
+                    info!("Sync worker stopped");
+                    break;
+                },
             }
         }
     }
 }
 
 
+// Source configuration to convert audit log entries to secret operations
+pub struct AuditFilter {
+    mounts: Vec<String>,
+    prefix: String,
+    version: EngineVersion,
+    namespace: String,
+    child_namespaces: Vec<String>,
+}
+
+impl AuditFilter {
+    pub fn new(src: &VaultSource) -> AuditFilter {
+        AuditFilter {
+            mounts: get_backends(&src.backend),
+            prefix: src.prefix.clone(),
+            version: src.version.clone(),
+            namespace: normalize_namespace(src.namespace.as_deref().unwrap_or_default()),
+            child_namespaces: src.child_namespaces.iter()
+                .flat_map(|namespaces| namespaces.keys())
+                .map(|namespace| normalize_namespace(namespace))
+                .collect(),
+        }
+    }
+
+    // Returns Some(None) if the audit log entry is for the source namespace, Some(child) if it is
+    // for one of the child namespaces, None if it is for any other namespace
+    fn namespace(&self, log: &audit::AuditLog) -> Option<Option<String>> {
+        let namespace = log.request.namespace.as_ref()
+            .and_then(|namespace| namespace.path.as_deref())
+            .map(normalize_namespace)
+            .unwrap_or_default();
+        if namespace == self.namespace {
+            return Some(None);
+        }
+        let child = if self.namespace.is_empty() {
+            namespace.as_str()
+        } else {
+            namespace.strip_prefix(&self.namespace)?.strip_prefix('/')?
+        };
+        if self.child_namespaces.iter().any(|namespace| namespace == child) {
+            Some(Some(child.to_string()))
+        } else {
+            None
+        }
+    }
+}
+
 // Convert AuditLog to SecretOp
-fn audit_log_op(mounts: &Vec<String>, prefix: &str, version: &EngineVersion, log: &audit::AuditLog) -> Option<SecretOp> {
+pub fn audit_log_op(filter: &AuditFilter, log: &audit::AuditLog) -> Option<SecretOp> {
     if log.log_type != "response" {
         return None;
     }
-    if log.request.mount_type.is_none() {
-        return None;
-    }
+    log.request.mount_type.as_ref()?;
     if log.request.mount_type != Some("kv".to_string()) {
         return None;
     }
 
     let operation = log.request.operation.clone();
-    if operation != "create" && operation != "update" && operation != "delete" {
+    if operation != "create" && operation != "update" && operation != "patch" && operation != "delete" {
         return None;
     }
 
-    let path = match version {
+    let namespace = filter.namespace(log)?;
+    let path = match filter.version {
         EngineVersion::V1 => secret_path_v1(&log.request.path),
         EngineVersion::V2 => secret_path_v2(&log.request.path),
     };
     if let Some(path) = path {
-        if !mounts.contains(&path.0) {
+        if !filter.mounts.contains(&path.0) {
             return None;
         }
-        if !path.1.starts_with(prefix) {
+        if !path.1.starts_with(&filter.prefix) {
             return None;
         }
+        let path = SecretPath { mount: path.0, path: path.1, namespace };
         if operation == "create" {
-            return Some(SecretOp::Create(SecretPath {mount: path.0, path: path.1 }));
-        } else if operation == "update" {
-            return Some(SecretOp::Update(SecretPath {mount: path.0, path: path.1 }));
+            return Some(SecretOp::Create(path));
+        } else if operation == "update" || operation == "patch" {
+            return Some(SecretOp::Update(path));
         } else if operation == "delete" {
-            return Some(SecretOp::Delete(SecretPath {mount: path.0, path: path.1 }));
+            return Some(SecretOp::Delete(path));
         }
     }
     None
@@ -339,8 +558,8 @@ fn secret_path_v2(path: &str) -> Option<(String, String)> {
     }
 }
 
-fn normalize_prefix(prefix: &str) -> String {
-    if prefix.len() == 0 {
+pub fn normalize_prefix(prefix: &str) -> String {
+    if prefix.is_empty() {
         return "".to_string();
     }
     if prefix.ends_with("/") {
@@ -350,11 +569,125 @@ fn normalize_prefix(prefix: &str) -> String {
     }
 }
 
+// Returns JSON merge patch (RFC 7386) that changes the destination secret to the source secret,
+// or None if the change cannot be expressed as a merge patch (for example, the source secret
+// contains null values, which in a merge patch mean removing the key)
+fn secret_merge_patch(dst: &Value, src: &Value) -> Option<Value> {
+    match (dst, src) {
+        (Value::Object(dst), Value::Object(src)) => {
+            let mut patch = serde_json::Map::new();
+            for (key, src_value) in src {
+                match dst.get(key) {
+                    Some(dst_value) if dst_value == src_value => {},
+                    Some(dst_value @ Value::Object(_)) if src_value.is_object() => {
+                        patch.insert(key.clone(), secret_merge_patch(dst_value, src_value)?);
+                    },
+                    _ => {
+                        if contains_null(src_value) {
+                            return None;
+                        }
+                        patch.insert(key.clone(), src_value.clone());
+                    },
+                }
+            }
+            for key in dst.keys() {
+                if !src.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            Some(Value::Object(patch))
+        },
+        _ => None,
+    }
+}
+
+fn contains_null(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Object(map) => map.values().any(contains_null),
+        _ => false,
+    }
+}
+
+// Remove leading and trailing slashes from a namespace
+// Example: "/team/app/" -> "team/app"
+fn normalize_namespace(namespace: &str) -> String {
+    namespace.trim_matches('/').to_string()
+}
+
+// Source namespace, followed by the configured child namespaces
+pub fn source_namespaces(src: &VaultSource) -> Vec<Option<String>> {
+    let mut namespaces = vec![None];
+    if let Some(child_namespaces) = &src.child_namespaces {
+        namespaces.extend(child_namespaces.keys().map(|namespace| Some(normalize_namespace(namespace))));
+    }
+    namespaces
+}
+
+// True for the source namespace itself and the configured child namespaces
+pub fn is_child_namespace(src: &VaultSource, child_namespace: &Option<String>) -> bool {
+    match child_namespace {
+        Some(child) => src.child_namespaces.iter()
+            .flat_map(|namespaces| namespaces.keys())
+            .any(|namespace| &normalize_namespace(namespace) == child),
+        None => true,
+    }
+}
+
+// Secret to sync on request (the sync-path command and the admin API), the namespace must be the
+// source namespace or one of the configured child namespaces, and the mount one of the source
+// backends
+// Example: "secret", "path/to/secret", "/app1/" -> secret/path/to/secret in the app1 namespace
+pub fn requested_secret_path(src: &VaultSource, mount: &str, path: &str, namespace: Option<&str>) -> Result<SecretPath, String> {
+    let namespace = namespace.map(normalize_namespace).filter(|namespace| !namespace.is_empty());
+    if !is_child_namespace(src, &namespace) {
+        return Err(format!("namespace {} is not one of src.child_namespaces", namespace.unwrap()));
+    }
+    let mount = mount.trim_matches('/');
+    if !get_backends(&src.backend).iter().any(|backend| backend == mount) {
+        return Err(format!("mount {} is not one of src.backend", mount));
+    }
+    Ok(SecretPath {
+        mount: mount.to_string(),
+        path: path.to_string(),
+        namespace,
+    })
+}
+
+// Full path of the source namespace for a child namespace
+// Example: "team", "app" -> "team/app"
+pub fn src_namespace(src: &VaultSource, child_namespace: &Option<String>) -> Option<String> {
+    match child_namespace {
+        Some(child) => match src.namespace.as_deref().map(normalize_namespace) {
+            Some(namespace) if !namespace.is_empty() => Some(format!("{}/{}", namespace, child)),
+            _ => Some(child.clone()),
+        },
+        None => src.namespace.clone(),
+    }
+}
+
+// Destination namespace for a child namespace of the source namespace.
+// The child namespace must be configured, see `is_child_namespace`.
+pub fn dst_namespace(config: &VaultSyncConfig, child_namespace: &Option<String>) -> Option<String> {
+    match child_namespace {
+        Some(child) => config.src.child_namespaces.iter()
+            .flat_map(|namespaces| namespaces.iter())
+            .find(|(namespace, _)| &normalize_namespace(namespace) == child)
+            .map(|(_, dst_namespace)| dst_namespace.clone()),
+        None => config.dst.namespace.clone(),
+    }
+}
+
+// Destination secret path for a source secret path, the same for the updates and the deletes
+fn dst_secret_path(config: &VaultSyncConfig, src_path: &str) -> String {
+    secret_src_to_dst_path(&normalize_prefix(&config.src.prefix), &normalize_prefix(&config.dst.prefix), src_path)
+}
+
 // Convert source secret path to destination secret path. Prefixes must be normalized!
 // Example: "src/secret1" -> "dst/secret2"
 fn secret_src_to_dst_path(src_prefix: &str, dst_prefix: &str, path: &str) -> String {
     let mut path = path.to_string();
-    if src_prefix.len() > 0 {
+    if !src_prefix.is_empty() {
         path = path.trim_start_matches(src_prefix).to_string();
     }
     format!("{}{}", dst_prefix, &path)
@@ -362,12 +695,55 @@ fn secret_src_to_dst_path(src_prefix: &str, dst_prefix: &str, path: &str) -> Str
 
 #[cfg(test)]
 mod tests {
-    use crate::sync::{normalize_prefix, secret_path_v1, secret_path_v2, secret_src_to_dst_path};
+    use crate::audit::AuditLog;
+    use crate::config::{VaultSource, VaultSyncConfig};
+    use serde_json::json;
+
+    use crate::sync::{audit_log_op, normalize_prefix, secret_merge_patch, secret_path_v1, secret_path_v2, secret_src_to_dst_path, src_namespace, is_child_namespace, requested_secret_path, dst_secret_path, AuditFilter, SecretOp, SyncStats};
+
+    fn audit_log(namespace: Option<&str>, path: &str) -> AuditLog {
+        audit_log_operation(namespace, "update", path)
+    }
+
+    fn audit_log_operation(namespace: Option<&str>, operation: &str, path: &str) -> AuditLog {
+        let namespace = match namespace {
+            Some(namespace) => format!(r#", "namespace": {{"id": "ns1", "path": "{}"}}"#, namespace),
+            None => r#", "namespace": {"id": "root"}"#.to_string(),
+        };
+        let json = format!(
+            r#"{{"time": "", "type": "response", "request": {{"operation": "{}", "mount_type": "kv", "path": "{}"{}}}}}"#,
+            operation,
+            path,
+            namespace,
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn source(namespace: Option<&str>) -> VaultSource {
+        let yaml = format!(
+            r#"
+                url: http://127.0.0.1:8200/
+                backend: secret
+                child_namespaces:
+                  app1: dst/app1
+                {}
+            "#,
+            namespace.map_or("".to_string(), |ns| format!("namespace: {}", ns)),
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    fn op_namespace(op: Option<SecretOp>) -> Option<Option<String>> {
+        match op {
+            Some(SecretOp::Update(path)) => Some(path.namespace),
+            _ => None,
+        }
+    }
 
     #[test]
     fn test_secret_path_v1_matches() {
         let path = "secret/path/to/secret";
-        let path = secret_path_v1(&path).unwrap();
+        let path = secret_path_v1(path).unwrap();
         assert_eq!(path.0, "secret");
         assert_eq!(path.1, "path/to/secret");
     }
@@ -375,7 +751,7 @@ mod tests {
     #[test]
     fn test_custom_secret_path_v1_matches() {
         let path = "custom/path/to/secret";
-        let path = secret_path_v1(&path).unwrap();
+        let path = secret_path_v1(path).unwrap();
         assert_eq!(path.0, "custom");
         assert_eq!(path.1, "path/to/secret");
     }
@@ -383,14 +759,14 @@ mod tests {
     #[test]
     fn test_secret_path_v1_not_matches() {
         let path = "secret";
-        let path = secret_path_v1(&path);
-        assert_eq!(path.is_none(), true);
+        let path = secret_path_v1(path);
+        assert!(path.is_none());
     }
 
     #[test]
     fn test_secret_path_v2_matches() {
         let path = "secret/data/path/to/secret";
-        let path = secret_path_v2(&path).unwrap();
+        let path = secret_path_v2(path).unwrap();
         assert_eq!(path.0, "secret");
         assert_eq!(path.1, "path/to/secret");
     }
@@ -398,7 +774,7 @@ mod tests {
     #[test]
     fn test_custom_secret_path_v2_matches() {
         let path = "custom/data/path/to/secret";
-        let path = secret_path_v2(&path).unwrap();
+        let path = secret_path_v2(path).unwrap();
         assert_eq!(path.0, "custom");
         assert_eq!(path.1, "path/to/secret");
     }
@@ -406,8 +782,8 @@ mod tests {
     #[test]
     fn test_secret_path_v2_not_matches() {
         let path = "secret/metadata/path/to/secret";
-        let path = secret_path_v2(&path);
-        assert_eq!(path.is_none(), true);
+        let path = secret_path_v2(path);
+        assert!(path.is_none());
     }
 
     #[test]
@@ -424,4 +800,108 @@ mod tests {
         assert_eq!(secret_src_to_dst_path("", "", "src/secret"), "src/secret");
     }
 
+    #[test]
+    fn test_audit_log_op_root_namespace() {
+        let filter = AuditFilter::new(&source(None));
+        let op = audit_log_op(&filter, &audit_log(None, "secret/data/foo"));
+        assert_eq!(op_namespace(op), Some(None));
+        let op = audit_log_op(&filter, &audit_log(Some("app1/"), "secret/data/foo"));
+        assert_eq!(op_namespace(op), Some(Some("app1".to_string())));
+        let op = audit_log_op(&filter, &audit_log(Some("app2/"), "secret/data/foo"));
+        assert!(op.is_none());
+    }
+
+    #[test]
+    fn test_audit_log_op_namespace() {
+        let filter = AuditFilter::new(&source(Some("team")));
+        let op = audit_log_op(&filter, &audit_log(Some("team/"), "secret/data/foo"));
+        assert_eq!(op_namespace(op), Some(None));
+        let op = audit_log_op(&filter, &audit_log(Some("team/app1/"), "secret/data/foo"));
+        assert_eq!(op_namespace(op), Some(Some("app1".to_string())));
+        let op = audit_log_op(&filter, &audit_log(None, "secret/data/foo"));
+        assert!(op.is_none());
+        let op = audit_log_op(&filter, &audit_log(Some("other/"), "secret/data/foo"));
+        assert!(op.is_none());
+        let op = audit_log_op(&filter, &audit_log(Some("team2/app1/"), "secret/data/foo"));
+        assert!(op.is_none());
+    }
+
+    #[test]
+    fn test_src_namespace() {
+        let child = Some("app1".to_string());
+        assert_eq!(src_namespace(&source(None), &None), None);
+        assert_eq!(src_namespace(&source(None), &child), Some("app1".to_string()));
+        assert_eq!(src_namespace(&source(Some("team")), &None), Some("team".to_string()));
+        assert_eq!(src_namespace(&source(Some("team/")), &child), Some("team/app1".to_string()));
+    }
+
+    #[test]
+    fn test_sync_stats() {
+        let mut stats = SyncStats::new();
+        stats.record("delete", "dry_run");
+        stats.record("update", "dry_run");
+        stats.record("update", "unchanged");
+        assert_eq!((stats.updated, stats.deleted), (0, 0));
+        stats.record("delete", "success");
+        stats.record("create", "success");
+        stats.record("delete", "error");
+        assert_eq!((stats.updated, stats.deleted), (1, 1));
+    }
+
+    #[test]
+    fn test_dst_secret_path() {
+        let yaml = r#"
+            id: vault-sync
+            full_sync_interval: 60
+            src:
+              url: http://127.0.0.1:8200/
+              prefix: src
+            dst:
+              url: http://127.0.0.1:8200/
+              prefix: dst
+        "#;
+        let config: VaultSyncConfig = serde_yaml::from_str(yaml).unwrap();
+        // Deletes go to the destination path, not the source path
+        assert_eq!(dst_secret_path(&config, "src/foo"), "dst/foo");
+        assert_eq!(dst_secret_path(&config, "src/team/app/"), "dst/team/app/");
+    }
+
+    #[test]
+    fn test_is_child_namespace() {
+        assert!(is_child_namespace(&source(None), &None));
+        assert!(is_child_namespace(&source(None), &Some("app1".to_string())));
+        assert!(!is_child_namespace(&source(None), &Some("app2".to_string())));
+    }
+
+    #[test]
+    fn test_requested_secret_path() {
+        let src = source(None);
+        let path = requested_secret_path(&src, "secret", "path/to/secret", Some("/app1/")).unwrap();
+        assert_eq!(path.namespace.as_deref(), Some("app1"));
+        assert_eq!(path.mount, "secret");
+        assert_eq!(requested_secret_path(&src, "secret/", "path/to/secret", None).unwrap().mount, "secret");
+        assert_eq!(requested_secret_path(&src, "secret", "path/to/secret", Some("/")).unwrap().namespace, None);
+        assert!(requested_secret_path(&src, "secret", "path/to/secret", Some("app2")).is_err());
+        assert!(requested_secret_path(&src, "kv", "path/to/secret", None).is_err());
+    }
+
+    #[test]
+    fn test_audit_log_op_patch() {
+        let filter = AuditFilter::new(&source(None));
+        let op = audit_log_op(&filter, &audit_log_operation(None, "patch", "secret/data/foo"));
+        assert!(matches!(op, Some(SecretOp::Update(_))));
+        let op = audit_log_op(&filter, &audit_log_operation(None, "read", "secret/data/foo"));
+        assert!(op.is_none());
+    }
+
+    #[test]
+    fn test_secret_merge_patch() {
+        let dst = json!({"a": "1", "b": "2", "c": {"d": "3", "e": "4"}});
+        let src = json!({"a": "1", "b": "5", "c": {"d": "3"}, "f": "6"});
+        assert_eq!(secret_merge_patch(&dst, &src), Some(json!({"b": "5", "c": {"e": null}, "f": "6"})));
+        assert_eq!(secret_merge_patch(&dst, &dst), Some(json!({})));
+        assert_eq!(secret_merge_patch(&dst, &json!({"a": null})), None);
+        assert_eq!(secret_merge_patch(&dst, &json!({"c": {"d": null}})), None);
+        assert_eq!(secret_merge_patch(&dst, &json!("string")), None);
+    }
 }
@@ -1,49 +1,220 @@
-use std::{thread, time};
+use std::{env, fs, thread, time};
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::time::Duration;
 
 use hashicorp_vault::client as vault;
-use hashicorp_vault::client::{SecretsEngine, TokenData, VaultDuration};
+use hashicorp_vault::reqwest;
+use hashicorp_vault::url::Url;
+use hashicorp_vault::client::{EndpointResponse, HttpVerb, SecretsEngine, TokenData, VaultDuration};
 use hashicorp_vault::client::error::Result as VaultResult;
 use log::{info, warn};
+use serde_json::Value;
 
-use crate::config::{EngineVersion, VaultAuthMethod, VaultHost};
+use crate::config::{CertAuth, EngineVersion, SharedConfig, VaultAuthMethod, VaultHost, VaultSyncConfig};
+use crate::health::HEALTH;
+use crate::metrics::METRICS;
 
 pub type VaultClient = hashicorp_vault::client::VaultClient<TokenData>;
 
+// How many times to retry a Vault request that timed out
+const TIMEOUT_RETRIES: u32 = 2;
+// Interval to retry a failed login for a token that is not renewable
+const LOGIN_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
 pub fn vault_client(host: &VaultHost, version: &EngineVersion, namespace: Option<String>) -> VaultResult<vault::VaultClient<TokenData>> {
-    let mut result = match host.auth.as_ref().unwrap() {
-        VaultAuthMethod::TokenAuth { token } => {
-            VaultClient::new(&host.url, token, namespace)
+    // The same HTTP client is used for the login and for the token
+    let (url, http_client) = http_client(host)?;
+    let token = match host.auth.as_ref().unwrap() {
+        VaultAuthMethod::TokenAuth { token } => token.clone(),
+        VaultAuthMethod::AppRoleAuth { role_id, secret_id, secret_id_wrapped } => {
+            let secret_id = if *secret_id_wrapped {
+                unwrap_secret_id(&url, &http_client, secret_id, &namespace)?
+            } else {
+                secret_id.clone()
+            };
+            vault::VaultClient::new_app_role_from_reqwest(
+                &url, role_id, Some(secret_id), http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::TokenFileAuth { token_file } => read_credential(None, None, Some(token_file))?,
+        VaultAuthMethod::AppRoleFileAuth { role_id, role_id_file, secret_id_file, secret_id_wrapped } => {
+            let role_id = read_credential(role_id.as_ref(), None, role_id_file.as_ref())?;
+            let mut secret_id = read_credential(None, None, Some(secret_id_file))?;
+            if *secret_id_wrapped {
+                secret_id = unwrap_secret_id(&url, &http_client, &secret_id, &namespace)?;
+            }
+            vault::VaultClient::new_app_role_from_reqwest(
+                &url, role_id, Some(secret_id), http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::KubernetesAuth { kubernetes } => {
+            // The projected service account token is rotated by the kubelet, read it on every login
+            let jwt = fs::read_to_string(&kubernetes.jwt_path)
+                .map_err(|error| vault::error::Error::Vault(format!("Failed to read {}: {}", &kubernetes.jwt_path, error)))?;
+            vault::VaultClient::new_kubernetes(
+                &url, &kubernetes.mount, &kubernetes.role, jwt.trim(), http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::CertAuth { cert } => {
+            vault::VaultClient::new_cert(
+                &url, &cert.mount, cert.name.as_ref(), http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::UserpassAuth { userpass } => {
+            let password = read_credential(userpass.password.as_ref(), userpass.password_env.as_ref(), userpass.password_file.as_ref())?;
+            let mount = userpass.mount.as_deref().unwrap_or("userpass");
+            vault::VaultClient::new_userpass(
+                &url, mount, &userpass.username, password, http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::LdapAuth { ldap } => {
+            let password = read_credential(ldap.password.as_ref(), ldap.password_env.as_ref(), ldap.password_file.as_ref())?;
+            let mount = ldap.mount.as_deref().unwrap_or("ldap");
+            vault::VaultClient::new_ldap(
+                &url, mount, &ldap.username, password, http_client.clone(), namespace.clone())?.token
+        },
+        VaultAuthMethod::JwtAuth { jwt } => {
+            // Workload identity tokens are short-lived, read the token on every login
+            let token = read_credential(None, jwt.env.as_ref(), jwt.path.as_ref())?;
+            vault::VaultClient::new_jwt(
+                &url, &jwt.mount, jwt.role.as_ref(), token, http_client.clone(), namespace.clone())?.token
         },
-        VaultAuthMethod::AppRoleAuth { role_id, secret_id} => {
-            let client = vault::VaultClient::new_app_role(
-                &host.url, role_id, Some(secret_id), namespace.clone())?;
-            VaultClient::new(&host.url, client.token, namespace)
-        }
     };
 
-    if let Ok(client) = &mut result {
-        client.secrets_engine(
-            match version {
-                EngineVersion::V1 => SecretsEngine::KVV1,
-                EngineVersion::V2 => SecretsEngine::KVV2,
+    let mut client = VaultClient::new_from_reqwest(&url, token, http_client, namespace)?;
+    client.secrets_engine(
+        match version {
+            EngineVersion::V1 => SecretsEngine::KVV1,
+            EngineVersion::V2 => SecretsEngine::KVV2,
+        }
+    );
+    Ok(client)
+}
+
+// Returns the URL to connect to and the HTTP client with the TLS configuration of the host. For
+// Cert auth method, the client presents the certificate on all requests, because Vault may require
+// it not only for the login.
+fn http_client(host: &VaultHost) -> VaultResult<(Url, reqwest::blocking::Client)> {
+    let mut url = Url::parse(&host.url)?;
+    let mut builder = reqwest::blocking::Client::builder();
+    if let Some(timeout) = host.timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(connect_timeout) = host.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(connect_timeout));
+    }
+    if let Some(proxy) = &host.proxy {
+        let no_proxy = host.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string);
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?.no_proxy(no_proxy));
+    }
+    if let Some(pool_idle_timeout) = host.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_secs(pool_idle_timeout));
+    }
+    if let Some(pool_max_idle) = host.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(pool_max_idle);
+    }
+    if let Some(tls) = &host.tls {
+        if let Some(ca_cert) = &tls.ca_cert {
+            for cert in reqwest::Certificate::from_pem_bundle(&fs::read(ca_cert)?)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(ca_path) = &tls.ca_path {
+            for entry in fs::read_dir(ca_path)? {
+                let path = entry?.path();
+                if !path.is_file() {
+                    continue;
+                }
+                match reqwest::Certificate::from_pem_bundle(&fs::read(&path)?) {
+                    Ok(certs) => for cert in certs {
+                        builder = builder.add_root_certificate(cert);
+                    },
+                    Err(error) => warn!(path:% = path.display(), error:%; "Skipping CA certificate"),
+                }
             }
-        );
+        }
+        if let (Some(cert), Some(key)) = (&tls.client_cert, &tls.client_key) {
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(&fs::read(cert)?, &fs::read(key)?)?);
+        }
+        if let Some(server_name) = &tls.server_name {
+            // Connects to the address of the host in the URL, but sends and verifies server_name.
+            // The address is resolved once for this client, the login builds a new client.
+            let addrs = url.socket_addrs(|| None)?;
+            builder = builder.resolve_to_addrs(server_name, &addrs);
+            url.set_host(Some(server_name))?;
+        }
+        if tls.insecure_skip_verify {
+            warn!(url = host.url.as_str(); "TLS certificate verification is disabled");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+    }
+    if let Some(VaultAuthMethod::CertAuth { cert }) = &host.auth {
+        builder = builder.identity(cert_identity(cert)?);
+    }
+    Ok((url, builder.build()?))
+}
+
+// Wrapping token -> unwrapped secret_id. A wrapping token can be unwrapped only once, and the
+// secret_id is needed again to request a new token or after the configuration is reloaded.
+static UNWRAPPED_SECRET_IDS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+// Returns the AppRole secret_id from the response wrapping token
+fn unwrap_secret_id(url: &Url, http_client: &reqwest::blocking::Client, wrapping_token: &str, namespace: &Option<String>) -> VaultResult<String> {
+    if let Some(secret_id) = UNWRAPPED_SECRET_IDS.lock().unwrap().get(wrapping_token) {
+        return Ok(secret_id.clone());
     }
+    info!("Unwrapping AppRole secret_id");
+    let client = vault::VaultClient::new_no_lookup_from_reqwest(url, wrapping_token, http_client.clone(), namespace.clone())?;
+    let response = client.call_endpoint::<Value>(HttpVerb::POST, "sys/wrapping/unwrap", None, None)
+        .map_err(|error| match error {
+            // Vault responds with 400 if the wrapping token does not exist
+            vault::error::Error::VaultResponse(_, response) if response.status().as_u16() == 400 => vault::error::Error::Vault(
+                "Failed to unwrap secret_id: the wrapping token is invalid, expired or already used, it may have been intercepted".to_string(),
+            ),
+            error => error,
+        })?;
+    let secret_id = match response {
+        EndpointResponse::VaultResponse(response) => response.data
+            .and_then(|data| data["secret_id"].as_str().map(|secret_id| secret_id.to_string())),
+        EndpointResponse::Empty => None,
+    };
+    let secret_id = secret_id.ok_or_else(|| vault::error::Error::Vault("No secret_id in the unwrapped response".to_string()))?;
+    UNWRAPPED_SECRET_IDS.lock().unwrap().insert(wrapping_token.to_string(), secret_id.clone());
+    Ok(secret_id)
+}
 
-    result
+// Returns the credential set in the configuration, or reads it from the environment variable or the
+// file. The credentials are read on every login, so they can be rotated without restart.
+fn read_credential(value: Option<&String>, env: Option<&String>, path: Option<&String>) -> VaultResult<String> {
+    let credential = match (value, env, path) {
+        (Some(value), _, _) => Ok(value.clone()),
+        (None, Some(env), _) => env::var(env)
+            .map_err(|error| format!("Failed to read environment variable {}: {}", env, error)),
+        (None, None, Some(path)) => fs::read_to_string(path)
+            .map(|content| content.trim().to_string())
+            .map_err(|error| format!("Failed to read {}: {}", path, error)),
+        (None, None, None) => Err("Credential is not set".to_string()),
+    };
+    credential.map_err(vault::error::Error::Vault)
 }
 
-// Worker to renew a Vault token lease, or to request a new token (for Vault AppRole auth method)
-pub fn token_worker(host: &VaultHost, version: &EngineVersion, client: Arc<Mutex<VaultClient>>, namespace: Option<String>) {
+fn cert_identity(cert: &CertAuth) -> VaultResult<reqwest::Identity> {
+    Ok(reqwest::Identity::from_pkcs8_pem(&fs::read(&cert.cert)?, &fs::read(&cert.key)?)?)
+}
+
+// Worker to renew a Vault token lease, or to request a new token (for the auth methods other than
+// Token)
+// The reload wakes the worker when it replaces the client, so the new token is planned at once
+pub fn token_worker(side: &str, config: SharedConfig, client: Arc<Mutex<VaultClient>>, wake: mpsc::Receiver<()>) {
     let mut token_age = time::Instant::now();
+    let mut login_failed = false;
     loop {
+        // The configuration can be reloaded while the worker is sleeping
+        let current_config = config.get();
+        let (host, version, namespace) = vault_side(&current_config, side);
         let info = {
             let client = client.lock().unwrap();
             TokenInfo::from_client(&client)
         };
-        info!("Token: {:?}", &info);
+        info!(side, token:? = info; "Token");
+        METRICS.set_token_ttl(side, info.ttl);
 
         // Override token TTL and max TTL with optional values from config
         let mut plan = info.clone();
@@ -71,73 +242,134 @@ pub fn token_worker(host: &VaultHost, version: &EngineVersion, client: Arc<Mutex
                 }
             }
         }
-        info!("Plan: {:?}", &plan);
+        info!(side, plan:?; "Plan");
 
-        if !plan.renewable {
+        // A token that is not renewable is replaced with a new one at TTL/2, if the auth method
+        // can request a new token (or re-read the token file)
+        let can_login = host.auth.as_ref().is_some_and(|auth| auth.can_login());
+        if !plan.renewable && !can_login {
             return;
-        } else {
-            if let Some(VaultAuthMethod::AppRoleAuth { role_id: _, secret_id: _ }) = &host.auth {
-                if plan.max_ttl.is_none() {
-                    warn!("Auth method is AppRole, but max_ttl is not set, using 32 days instead");
-                    plan.max_ttl = Some(time::Duration::from_secs(32 * 24 * 60 * 60));
-                }
+        }
+        if let Some(auth) = host.auth.as_ref().filter(|auth| auth.can_login()) {
+            if plan.max_ttl.is_none() {
+                warn!(side, auth = auth.name(); "max_ttl is not set, using 32 days instead");
+                plan.max_ttl = Some(time::Duration::from_secs(32 * 24 * 60 * 60));
             }
-            if let Some(VaultAuthMethod::TokenAuth { token: _ }) = &host.auth {
-                if plan.max_ttl.is_some() {
-                    info!("Auth method is Token, but max_ttl is set, ignoring");
-                    plan.max_ttl = None;
-                }
+        }
+        if let Some(VaultAuthMethod::TokenAuth { token: _ }) = &host.auth {
+            if plan.max_ttl.is_some() {
+                info!(side, auth = "Token"; "max_ttl is set, ignoring");
+                plan.max_ttl = None;
             }
         }
 
         let duration = {
-            if plan.ttl.is_none() {
-                plan.max_ttl.unwrap()
-            } else if plan.max_ttl.is_none() {
-                plan.ttl.unwrap()
-            } else {
-                plan.ttl.unwrap().min(plan.max_ttl.unwrap())
+            match (plan.ttl, plan.max_ttl) {
+                (Some(ttl), Some(max_ttl)) => ttl.min(max_ttl),
+                (Some(ttl), None) => ttl,
+                (None, max_ttl) => max_ttl.unwrap(),
             }
         };
         let duration = time::Duration::from_secs(duration.as_secs() / 2);
+        // The token keeps expiring, do not wait another TTL/2 after a failed login
+        let duration = if login_failed { duration.min(LOGIN_RETRY_INTERVAL) } else { duration };
 
-        thread::sleep(duration);
+        match wake.recv_timeout(duration) {
+            Ok(()) => {
+                info!(side; "Client reconnected");
+                token_age = time::Instant::now();
+                login_failed = false;
+                continue;
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(duration),
+        }
 
         if let Some(max_ttl) = plan.max_ttl {
             let age = token_age.elapsed().as_secs();
             let max_ttl = max_ttl.as_secs();
-            if age > max_ttl / 2 {
-                if let Some(VaultAuthMethod::AppRoleAuth { role_id: _, secret_id: _ }) = &host.auth {
-                    info!("Requesting a new token");
-                    match vault_client(&host, &version, namespace.clone()) {
-                        Ok(new_client) => {
-                            let mut client = client.lock().unwrap();
-                            client.token = new_client.token;
-                            client.data = new_client.data;
-                            token_age = time::Instant::now();
-                            continue;
-                        },
-                        Err(error) => {
-                            warn!("Failed to request a new token: {}", error);
-                        }
-                    }
+            if (age > max_ttl / 2 || !plan.renewable) && can_login {
+                info!(side; "Requesting a new token");
+                login_failed = !login(side, host, version, namespace, &client);
+                if !login_failed {
+                    token_age = time::Instant::now();
+                    continue;
                 }
             }
         }
 
-        if let Some(_) = plan.ttl {
-            info!("Renewing token");
-            let result = {
+        if plan.ttl.is_some() && plan.renewable {
+            info!(side; "Renewing token");
+            let result = retry_on_timeout(|| {
                 let mut client = client.lock().unwrap();
+                // The sync workers switch the namespace for the child namespaces
+                client.namespace = namespace.clone();
                 client.renew()
-            };
-            if let Err(error) = result {
-                warn!("Failed to renew token: {}", error);
+            });
+            match result {
+                Ok(_) => HEALTH.set_token(side, true),
+                Err(error) => {
+                    warn!(side, error:%; "Failed to renew token");
+                    HEALTH.set_token(side, false);
+                    // The token was revoked or expired, the credentials may have been rotated
+                    if is_forbidden(&error) && can_login {
+                        info!(side; "Requesting a new token");
+                        login_failed = !login(side, host, version, namespace, &client);
+                        if !login_failed {
+                            token_age = time::Instant::now();
+                        }
+                    }
+                }
             }
         }
     }
 }
 
+// Retries the Vault request if it timed out, for example when the Vault server is overloaded
+pub fn retry_on_timeout<T>(mut request: impl FnMut() -> VaultResult<T>) -> VaultResult<T> {
+    let mut attempt = 0;
+    loop {
+        match request() {
+            Err(error) if error.is_timeout() && attempt < TIMEOUT_RETRIES => {
+                attempt += 1;
+                warn!(attempt, error:%; "Vault request timed out, retrying");
+                thread::sleep(Duration::from_secs(attempt as u64));
+            },
+            result => return result,
+        }
+    }
+}
+
+// Logs in again and replaces the client, returns true on success. The new HTTP client resolves the
+// host for tls.server_name again. The login is not retried on timeout, because unwrapping the
+// secret_id can succeed only once, the token worker tries again after LOGIN_RETRY_INTERVAL.
+fn login(side: &str, host: &VaultHost, version: &EngineVersion, namespace: &Option<String>, client: &Arc<Mutex<VaultClient>>) -> bool {
+    match vault_client(host, version, namespace.clone()) {
+        Ok(new_client) => {
+            *client.lock().unwrap() = new_client;
+            HEALTH.set_token(side, true);
+            true
+        },
+        Err(error) => {
+            warn!(side, error:%; "Failed to request a new token");
+            HEALTH.set_token(side, false);
+            false
+        }
+    }
+}
+
+fn is_forbidden(error: &vault::error::Error) -> bool {
+    matches!(error, vault::error::Error::VaultResponse(_, response) if response.status().as_u16() == 403)
+}
+
+// Returns the host, engine version and namespace for "src" or "dst"
+pub fn vault_side<'a>(config: &'a VaultSyncConfig, side: &str) -> (&'a VaultHost, &'a EngineVersion, &'a Option<String>) {
+    match side {
+        "src" => (&config.src.host, &config.src.version, &config.src.namespace),
+        _ => (&config.dst.host, &config.dst.version, &config.dst.namespace),
+    }
+}
+
 #[derive(Debug, Clone)]
 struct TokenInfo {
     renewable: bool,
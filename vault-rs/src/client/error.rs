@@ -7,10 +7,14 @@ quick_error! {
     pub enum Error {
         /// `reqwest::Error` errors
         Reqwest(err: ::reqwest::Error) {
-            from()
             display("reqwest error: {}", err)
             source(err)
         }
+        /// The request timed out, it can be retried
+        Timeout(err: Box<::reqwest::Error>) {
+            display("request timed out: {}", err)
+            source(err)
+        }
         /// `serde_json::Error`
         SerdeJson(err: ::serde_json::Error) {
             from()
@@ -23,7 +27,7 @@ quick_error! {
         }
         /// Response from Vault errors
         /// This is for when the response is not successful.
-        VaultResponse(err: String, response: reqwest::blocking::Response) {
+        VaultResponse(err: String, response: Box<reqwest::blocking::Response>) {
             display("Error in vault response: {}", err)
         }
         /// IO errors
@@ -46,3 +50,20 @@ quick_error! {
         }
     }
 }
+
+impl From<::reqwest::Error> for Error {
+    fn from(err: ::reqwest::Error) -> Error {
+        if err.is_timeout() {
+            Error::Timeout(Box::new(err))
+        } else {
+            Error::Reqwest(err)
+        }
+    }
+}
+
+impl Error {
+    /// Returns true if the request timed out
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::Timeout(_))
+    }
+}
@@ -482,6 +482,34 @@ struct AppRolePayload {
     secret_id: Option<String>,
 }
 
+/// Payload to send to vault when authenticating via `Kubernetes`
+#[derive(Deserialize, Serialize, Debug)]
+struct KubernetesPayload {
+    role: String,
+    jwt: String,
+}
+
+/// Payload to send to vault when authenticating via `JWT`
+#[derive(Deserialize, Serialize, Debug)]
+struct JwtPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    jwt: String,
+}
+
+/// Payload to send to vault when authenticating via `TLS Certificates`
+#[derive(Deserialize, Serialize, Debug)]
+struct CertPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// Payload to send to vault when authenticating via `Userpass` or `LDAP`
+#[derive(Deserialize, Serialize, Debug)]
+struct PasswordPayload {
+    password: String,
+}
+
 /// Postgresql secret backend
 #[derive(Deserialize, Serialize, Debug)]
 pub struct PostgresqlLogin {
@@ -800,15 +828,162 @@ impl VaultClient<()> {
         R: Into<String>,
         S: Into<String>,
     {
-        let host = host.try_into()?;
-        let client = Client::new();
+        VaultClient::new_app_role_from_reqwest(host, role_id, secret_id, Client::new(), namespace)
+    }
+
+    /// Construct a `VaultClient` via the `AppRole`
+    /// [auth backend](https://www.vaultproject.io/docs/auth/approle.html) and `reqwest::Client`
+    pub fn new_app_role_from_reqwest<U, R, S>(
+        host: U,
+        role_id: R,
+        secret_id: Option<S>,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        R: Into<String>,
+        S: Into<String>,
+    {
         let secret_id = secret_id.map(|s| s.into());
         let payload = serde_json::to_string(&AppRolePayload {
             role_id: role_id.into(),
             secret_id,
         })?;
+        VaultClient::login(host.try_into()?, cli, "approle/login", payload, namespace)
+    }
+
+    /// Construct a `VaultClient` via the `Kubernetes`
+    /// [auth backend](https://developer.hashicorp.com/vault/docs/auth/kubernetes), mounted at
+    /// `mount`, with the service account `jwt` and `reqwest::Client`
+    pub fn new_kubernetes<U, M, R, J>(
+        host: U,
+        mount: M,
+        role: R,
+        jwt: J,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        M: AsRef<str>,
+        R: Into<String>,
+        J: Into<String>,
+    {
+        let payload = serde_json::to_string(&KubernetesPayload {
+            role: role.into(),
+            jwt: jwt.into(),
+        })?;
+        let path = format!("{}/login", mount.as_ref());
+        VaultClient::login(host.try_into()?, cli, &path, payload, namespace)
+    }
+
+    /// Construct a `VaultClient` via the `JWT`
+    /// [auth backend](https://developer.hashicorp.com/vault/docs/auth/jwt), mounted at `mount`,
+    /// with `reqwest::Client`.
+    /// If `role` is not set, the default role of the auth backend is used.
+    pub fn new_jwt<U, M, R, J>(
+        host: U,
+        mount: M,
+        role: Option<R>,
+        jwt: J,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        M: AsRef<str>,
+        R: Into<String>,
+        J: Into<String>,
+    {
+        let payload = serde_json::to_string(&JwtPayload {
+            role: role.map(|r| r.into()),
+            jwt: jwt.into(),
+        })?;
+        let path = format!("{}/login", mount.as_ref());
+        VaultClient::login(host.try_into()?, cli, &path, payload, namespace)
+    }
+
+    /// Construct a `VaultClient` via the `TLS Certificates`
+    /// [auth backend](https://developer.hashicorp.com/vault/docs/auth/cert), mounted at `mount`.
+    /// The `reqwest::Client` must be built with the client certificate identity, see
+    /// `reqwest::blocking::ClientBuilder::identity`. If `name` is not set, Vault tries all the
+    /// certificate roles.
+    pub fn new_cert<U, M, N>(
+        host: U,
+        mount: M,
+        name: Option<N>,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        M: AsRef<str>,
+        N: Into<String>,
+    {
+        let payload = serde_json::to_string(&CertPayload {
+            name: name.map(|n| n.into()),
+        })?;
+        let path = format!("{}/login", mount.as_ref());
+        VaultClient::login(host.try_into()?, cli, &path, payload, namespace)
+    }
+
+    /// Construct a `VaultClient` via the `Userpass`
+    /// [auth backend](https://developer.hashicorp.com/vault/docs/auth/userpass), mounted at `mount`,
+    /// with `reqwest::Client`
+    pub fn new_userpass<U, M, N, P>(
+        host: U,
+        mount: M,
+        username: N,
+        password: P,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        M: AsRef<str>,
+        N: AsRef<str>,
+        P: Into<String>,
+    {
+        let payload = serde_json::to_string(&PasswordPayload {
+            password: password.into(),
+        })?;
+        let path = format!("{}/login/{}", mount.as_ref(), username.as_ref());
+        VaultClient::login(host.try_into()?, cli, &path, payload, namespace)
+    }
+
+    /// Construct a `VaultClient` via the `LDAP`
+    /// [auth backend](https://developer.hashicorp.com/vault/docs/auth/ldap), mounted at `mount`,
+    /// with `reqwest::Client`
+    pub fn new_ldap<U, M, N, P>(
+        host: U,
+        mount: M,
+        username: N,
+        password: P,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+        M: AsRef<str>,
+        N: AsRef<str>,
+        P: Into<String>,
+    {
+        // The LDAP login endpoint is the same as for Userpass
+        VaultClient::new_userpass(host, mount, username, password, cli, namespace)
+    }
+
+    /// Log in to the auth backend endpoint `auth/<path>` with `payload`, and construct a
+    /// `VaultClient` with the client token
+    fn login(
+        host: Url,
+        client: Client,
+        path: &str,
+        payload: String,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>> {
         let mut request = client
-            .post(host.join("/v1/auth/approle/login")?)
+            .post(host.join(&format!("/v1/auth/{}", path))?)
             .body(payload);
 
         if let Some(ns) = &namespace {
@@ -848,7 +1023,21 @@ impl VaultClient<()> {
     where
         U: TryInto<Url, Err = Error>,
     {
-        let client = Client::new();
+        VaultClient::new_no_lookup_from_reqwest(host, token, Client::new(), namespace)
+    }
+
+    /// Construct a `VaultClient` with `reqwest::Client`, where no lookup is done through vault,
+    /// see `new_no_lookup`.
+    pub fn new_no_lookup_from_reqwest<U, S: Into<String>>(
+        host: U,
+        token: S,
+        cli: Client,
+        namespace: Option<String>,
+    ) -> Result<VaultClient<()>>
+    where
+        U: TryInto<Url, Err = Error>,
+    {
+        let client = cli;
         let host = host.try_into()?;
         Ok(VaultClient {
             host,
@@ -1141,6 +1330,41 @@ where
         Ok(())
     }
 
+    /// Patches a secret with a [JSON merge patch](https://datatracker.ietf.org/doc/html/rfc7386):
+    /// only the keys present in the patch are changed, the keys with `null` values are removed.
+    /// Supported only for KV secrets engine v2.
+    ///
+    /// ```
+    /// # extern crate hashicorp_vault as vault;
+    /// # use vault::Client;
+    /// use serde_json::json;
+    ///
+    /// let host = "http://127.0.0.1:8200";
+    /// let token = "test12345";
+    /// let namespace: Option<String> = None;
+    /// let client = Client::new(host, token, namespace).unwrap();
+    /// let res = client.set_custom_secret("hello_patch", &json!({"foo": "bar", "baz": "qux"}));
+    /// assert!(res.is_ok());
+    /// let res = client.patch_custom_secret("hello_patch", &json!({"foo": "baz", "baz": null}));
+    /// assert!(res.is_ok());
+    /// ```
+    pub fn patch_custom_secret<S1, S2>(&self, secret_name: S1, patch: &S2) -> Result<()>
+    where
+        S1: Into<String>,
+        S2: Serialize,
+    {
+        let encoded_name = url_encode_path(&secret_name.into());
+        let endpoint = match self.secrets_engine {
+            SecretsEngine::KVV1 => {
+                return Err(Error::Vault("Patch is not supported for KV secrets engine v1".into()))
+            },
+            SecretsEngine::KVV2 => format!("/v1/{}/data/{}", self.secret_backend, encoded_name),
+        };
+        let json = serde_json::to_string(&SecretContainer { data: patch })?;
+        let _ = self.patch(&endpoint, Some(&json))?;
+        Ok(())
+    }
+
     ///
     /// List secrets at specified path
     ///
@@ -1630,6 +1854,21 @@ where
         }
     }
 
+    fn patch<S: AsRef<str>>(&self, endpoint: S, body: Option<&str>) -> Result<Response> {
+        let h = self.host.join(endpoint.as_ref())?;
+        let body = body.unwrap_or("").to_string();
+        let mut request = self.client
+            .request(Method::PATCH, h)
+            .header("X-Vault-Token", self.token.to_string())
+            .header(CONTENT_TYPE, "application/merge-patch+json");
+
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        Ok(handle_reqwest_response(request.body(body).send())?)
+    }
+
     fn list<S1: AsRef<str>, S2: Into<String>>(
         &self,
         endpoint: S1,
@@ -1713,7 +1952,7 @@ fn handle_reqwest_response(res: StdResult<Response, reqwest::Error>) -> Result<R
                 "Vault request failed: {:?}, error message: `{}`",
                 res, error_msg
             ),
-            res,
+            Box::new(res),
         ))
     }
 }
@@ -1756,7 +1995,7 @@ fn handle_reqwest_response(res: StdResult<Response, reqwest::Error>) -> Result<R
 ///                 "Vault request failed: {:?}, error message: `{}`",
 ///                 res, error_msg
 ///             ),
-///             res,
+///             Box::new(res),
 ///         ))
 ///     }
 /// }